@@ -0,0 +1,445 @@
+use axum::{
+    extract::{Extension, Path as UrlPath},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    editor::{generate_token, tokens_match},
+    error::{Error, IoResultExt, Result},
+    page::{FrontMatter, Post, Posts},
+    Config,
+};
+
+// json content api of `serve --api`
+pub const API_PATH: &str = "/__api";
+
+// token of the content api, generated for the run when it is not set
+pub const API_TOKEN_ENV: &str = "ONEPAGE_API_TOKEN";
+
+/**
+ * JSON api of `serve --api` to publish posts without shell access. Posts
+ * are read and written through their `FrontMatter`, every request needs
+ * the token as a bearer token.
+ */
+#[derive(Debug, Clone)]
+pub struct ContentApi {
+    config: Config,
+    token: String,
+    // printed at start when it was not set in the env
+    generated: bool,
+}
+
+/**
+ * A post of the api, `content` is its markdown without the front matter.
+ */
+#[derive(Debug, Serialize)]
+pub struct ApiPost {
+    // path in the posts dir, without `.md`
+    pub slug: String,
+    pub url: String,
+    #[serde(flatten)]
+    pub front_matter: FrontMatter,
+    pub content: String,
+}
+
+/**
+ * Body of a create or an update, a create without a date is dated now and
+ * an update without one keeps the date of the post.
+ */
+#[derive(Debug, Deserialize)]
+pub struct PostInput {
+    // only read by creates, derived from the title when missing
+    pub slug: Option<String>,
+    pub title: String,
+    pub date: Option<String>,
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub content: String,
+}
+
+impl ContentApi {
+    /**
+     * The token is read from `ONEPAGE_API_TOKEN`, generated when it is not set.
+     */
+    pub fn from_env(config: &Config) -> Result<Self> {
+        match env::var(API_TOKEN_ENV) {
+            Ok(token) if !token.trim().is_empty() => Ok(Self::new(config, token.trim())),
+            _ => Ok(Self {
+                generated: true,
+                ..Self::new(config, &generate_token()?)
+            }),
+        }
+    }
+
+    pub fn new(config: &Config, token: &str) -> Self {
+        Self {
+            config: config.clone(),
+            token: token.to_string(),
+            generated: false,
+        }
+    }
+
+    /**
+     * The token to print at start, `None` when it comes from the env.
+     */
+    pub fn generated_token(&self) -> Option<&str> {
+        self.generated.then_some(self.token.as_str())
+    }
+
+    /**
+     * Check an `Authorization: Bearer <token>` header.
+     */
+    pub fn is_authorized(&self, authorization: &str) -> bool {
+        authorization
+            .strip_prefix("Bearer ")
+            .is_some_and(|token| tokens_match(token.trim(), &self.token))
+    }
+
+    /**
+     * The posts which load, newest first like the index page. Like the
+     * site and the editor, posts in nested dirs are listed too.
+     */
+    pub fn list(&self) -> Vec<ApiPost> {
+        let mut posts = Posts::files(self.config.get_page_posts_path())
+            .into_iter()
+            .filter_map(|file| self.load(&file).ok())
+            .collect::<Vec<_>>();
+        posts.sort_by(|a, b| b.front_matter.date.cmp(&a.front_matter.date));
+        posts
+    }
+
+    pub fn get(&self, slug: &str) -> Result<ApiPost> {
+        self.load(&self.post_file(slug)?)
+    }
+
+    pub fn create(&self, input: PostInput) -> Result<ApiPost> {
+        let slug = match &input.slug {
+            Some(slug) => slug.clone(),
+            None => slugify(&input.title),
+        };
+        let file = self.post_file(&slug)?;
+        let date = input
+            .date
+            .clone()
+            .unwrap_or_else(|| FrontMatter::default().date);
+        // fails when the post exists, even when it was created meanwhile
+        self.write(&file, input, date, OpenOptions::new().create_new(true))
+    }
+
+    pub fn update(&self, slug: &str, input: PostInput) -> Result<ApiPost> {
+        let file = self.post_file(slug)?;
+        if !file.exists() {
+            return Err(Error::io(
+                file,
+                io::Error::new(io::ErrorKind::NotFound, "Post not found."),
+            ));
+        }
+        let date = match &input.date {
+            Some(date) => date.clone(),
+            None => {
+                let raw = fs::read_to_string(&file).with_path(&file)?;
+                Post::read_front_matter(&raw, &file)?.0.date
+            }
+        };
+        self.write(&file, input, date, OpenOptions::new().truncate(true))
+    }
+
+    pub fn delete(&self, slug: &str) -> Result<()> {
+        let file = self.post_file(slug)?;
+        fs::remove_file(&file).with_path(&file)
+    }
+
+    /**
+     * Write a post once it is valid and loads like the others, the watcher
+     * of the dev server rebuilds the site.
+     */
+    fn write(
+        &self,
+        file: &Path,
+        input: PostInput,
+        date: String,
+        options: &mut OpenOptions,
+    ) -> Result<ApiPost> {
+        let front_matter = FrontMatter {
+            title: input.title,
+            tags: input.tags,
+            date,
+        };
+        front_matter.validate(file)?;
+        let raw = front_matter.to_markdown(file, &input.content)?;
+        let post = self.api_post(file, &raw)?;
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).with_path(dir)?;
+        }
+        options
+            .write(true)
+            .open(file)
+            .and_then(|mut out| out.write_all(raw.as_bytes()))
+            .with_path(file)?;
+        Ok(post)
+    }
+
+    fn load(&self, file: &Path) -> Result<ApiPost> {
+        let raw = fs::read_to_string(file).with_path(file)?;
+        self.api_post(file, &raw)
+    }
+
+    fn api_post(&self, file: &Path, raw: &str) -> Result<ApiPost> {
+        let post = Post::parse_in(&self.config.page_dir, file, raw)?;
+        let (_, content) = Post::read_front_matter(raw, file)?;
+        let posts_dir = self.config.get_page_posts_path();
+        Ok(ApiPost {
+            slug: file
+                .strip_prefix(&posts_dir)
+                .unwrap_or(file)
+                .with_extension("")
+                .to_string_lossy()
+                .replace('\\', "/"),
+            url: post.url,
+            front_matter: post.front_matter,
+            content,
+        })
+    }
+
+    /**
+     * Slugs name files in the posts dir, `2022/hello` for a nested post.
+     */
+    fn post_file(&self, slug: &str) -> Result<PathBuf> {
+        let valid = slug.split('/').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        });
+        match valid {
+            true => Ok(self
+                .config
+                .get_page_posts_path()
+                .join(format!("{}.md", slug))),
+            false => Err(Error::Server(format!(
+                "\"{}\" is not a valid slug, use letters, digits, `-`, `_` and `/` between dirs",
+                slug
+            ))),
+        }
+    }
+}
+
+/**
+ * `Release notes: 1.0` is `release-notes-1-0`.
+ */
+fn slugify(title: &str) -> String {
+    title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/**
+ * The routes of the content api, merged into the dev routes of `serve --api`.
+ */
+pub fn routes(api: ContentApi) -> Router {
+    Router::new()
+        .route("/__api/posts", get(api_posts).post(api_create_post))
+        .route(
+            "/__api/posts/*slug",
+            get(api_post).put(api_update_post).delete(api_delete_post),
+        )
+        .layer(Extension(api))
+}
+
+// errors of the content api are json too, `{"error": "..."}`
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+type ApiResponse = std::result::Result<Response, ApiError>;
+
+// by name, the params hold the `path` of the app route too
+#[derive(Debug, Deserialize)]
+struct PostSlug {
+    // with the leading `/` of the wildcard
+    slug: String,
+}
+
+impl PostSlug {
+    fn slug(&self) -> &str {
+        self.slug.trim_start_matches('/')
+    }
+}
+
+async fn api_posts(Extension(api): Extension<ContentApi>, headers: HeaderMap) -> ApiResponse {
+    authorized_api(&api, &headers)?;
+    Ok(Json(api.list()).into_response())
+}
+
+async fn api_post(
+    Extension(api): Extension<ContentApi>,
+    headers: HeaderMap,
+    UrlPath(path): UrlPath<PostSlug>,
+) -> ApiResponse {
+    authorized_api(&api, &headers)?;
+    let post = api.get(path.slug()).map_err(api_error)?;
+    Ok(Json(post).into_response())
+}
+
+async fn api_create_post(
+    Extension(api): Extension<ContentApi>,
+    headers: HeaderMap,
+    Json(input): Json<PostInput>,
+) -> ApiResponse {
+    authorized_api(&api, &headers)?;
+    let post = api.create(input).map_err(api_error)?;
+    Ok((StatusCode::CREATED, Json(post)).into_response())
+}
+
+async fn api_update_post(
+    Extension(api): Extension<ContentApi>,
+    headers: HeaderMap,
+    UrlPath(path): UrlPath<PostSlug>,
+    Json(input): Json<PostInput>,
+) -> ApiResponse {
+    authorized_api(&api, &headers)?;
+    let post = api.update(path.slug(), input).map_err(api_error)?;
+    Ok(Json(post).into_response())
+}
+
+async fn api_delete_post(
+    Extension(api): Extension<ContentApi>,
+    headers: HeaderMap,
+    UrlPath(path): UrlPath<PostSlug>,
+) -> ApiResponse {
+    authorized_api(&api, &headers)?;
+    api.delete(path.slug()).map_err(api_error)?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/**
+ * Check the request has the bearer token of the content api.
+ */
+fn authorized_api(api: &ContentApi, headers: &HeaderMap) -> std::result::Result<(), ApiError> {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    match api.is_authorized(authorization) {
+        true => Ok(()),
+        false => Err(api_message(
+            StatusCode::UNAUTHORIZED,
+            "missing or invalid bearer token",
+        )),
+    }
+}
+
+/**
+ * Missing posts are a 404, existing ones a 409 when creating them, and
+ * invalid ones a 422.
+ */
+fn api_error(error: Error) -> ApiError {
+    let status = match &error {
+        Error::Io { source, .. } => match source.kind() {
+            io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            io::ErrorKind::AlreadyExists => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        },
+        _ => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+    api_message(status, &error.report())
+}
+
+fn api_message(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(serde_json::json!({ "error": message })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(title: &str, content: &str) -> PostInput {
+        PostInput {
+            slug: None,
+            title: title.to_string(),
+            date: Some("2022-04-01 09:00:00".to_string()),
+            tags: Some(vec!["release".to_string()]),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn content_api() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            page_dir: dir.path().join("pages"),
+            ..Config::default()
+        };
+        let api = ContentApi::new(&config, "secret");
+        assert!(api.is_authorized("Bearer secret"));
+        assert!(!api.is_authorized("secret"));
+        assert!(!api.is_authorized("Bearer nope"));
+        assert_eq!(api.generated_token(), None);
+
+        let created = api
+            .create(input("Release notes: 1.0", "# Notes\n"))
+            .unwrap();
+        assert_eq!(created.slug, "release-notes-1-0");
+        assert_eq!(created.url, "/posts/release-notes-1-0.html");
+        assert_eq!(created.content, "# Notes");
+        let file = config.get_page_posts_path().join("release-notes-1-0.md");
+        let (front_matter, _) =
+            Post::read_front_matter(&fs::read_to_string(&file).unwrap(), &file).unwrap();
+        assert_eq!(front_matter.title, "Release notes: 1.0");
+        let exists = api.create(input("Release notes: 1.0", "")).unwrap_err();
+        assert!(matches!(&exists, Error::Io { source, .. }
+            if source.kind() == io::ErrorKind::AlreadyExists));
+
+        let updated = api
+            .update("release-notes-1-0", input("Release notes", "fixed"))
+            .unwrap();
+        assert_eq!(updated.front_matter.title, "Release notes");
+        assert_eq!(api.get("release-notes-1-0").unwrap().content, "fixed");
+        let undated = PostInput {
+            date: None,
+            ..input("Release notes", "undated")
+        };
+        let updated = api.update("release-notes-1-0", undated).unwrap();
+        assert_eq!(updated.front_matter.date, "2022-04-01 09:00:00");
+        assert!(api.update("missing", input("Missing", "")).is_err());
+
+        let invalid = PostInput {
+            date: Some("soon".to_string()),
+            ..input("Invalid", "")
+        };
+        assert!(api.create(invalid).is_err());
+        for slug in ["", "../index", "a//b", "/a", "a.md"] {
+            assert!(api.get(slug).is_err(), "{}", slug);
+        }
+
+        // nested posts, listed like the site builds them
+        let nested = PostInput {
+            slug: Some("2022/launch".to_string()),
+            ..input("Launch", "")
+        };
+        assert_eq!(api.create(nested).unwrap().url, "/posts/2022/launch.html");
+        let slugs = api
+            .list()
+            .into_iter()
+            .map(|post| post.slug)
+            .collect::<Vec<_>>();
+        assert_eq!(slugs, vec!["2022/launch", "release-notes-1-0"]);
+        api.delete("2022/launch").unwrap();
+
+        assert_eq!(api.list().len(), 1);
+        api.delete("release-notes-1-0").unwrap();
+        assert!(api.list().is_empty());
+        assert!(api.delete("release-notes-1-0").is_err());
+    }
+}
@@ -1,10 +1,23 @@
-use anyhow::{bail, Result};
-use chrono::Local;
-use std::{fs, io::Write, path::Path};
+use chrono::{DateTime, Local};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use crate::{
-    page::{IndexPage, Posts},
-    templates, utils, Config, INDEX_TEMPLATE, POST_TEMPLATE,
+    cache::{self, BuildCache},
+    error::{Error, IoResultExt, Result},
+    output::{FsOutput, OutputSink},
+    page::{FrontMatter, IndexPage, Post, Posts},
+    templates::Templates,
+    utils, Config, INDEX_TEMPLATE, NOT_FOUND_PAGE, NOT_FOUND_TEMPLATE, POST_TEMPLATE,
 };
 
 pub trait LoadPage {
@@ -12,11 +25,29 @@ pub trait LoadPage {
     fn load<P: AsRef<Path>>(path: P) -> Result<Self::Item>;
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct SiteBuilder {
     pub config: Config,
     pub index: IndexPage,
     pub posts: Posts,
+    pub not_found: Option<IndexPage>,
+    // only report errors, eg. in watch mode which prints a line per rebuild
+    pub quiet: bool,
+    templates: Templates,
+    cache: BuildCache,
+    output: Box<dyn OutputSink>,
+    // set to stop the running build, eg. when newer changes supersede it
+    cancel: Arc<AtomicBool>,
+    // post pages rendered by the running build
+    rendered: Vec<PathBuf>,
+    // when each post page was last published, by output path
+    built_at: HashMap<PathBuf, DateTime<Local>>,
+}
+
+impl Default for SiteBuilder {
+    fn default() -> Self {
+        Self::with_config(Config::default())
+    }
 }
 
 impl SiteBuilder {
@@ -24,139 +55,454 @@ impl SiteBuilder {
         Self::default()
     }
 
-    pub fn load(&mut self) {
-        println!("🏃🏻 Loading posts ...");
-        let posts = Posts::load(self.config.get_page_posts_path()).unwrap_or_else(|e| {
-            println!("\n\n💥 Failed to load \"pages/posts/...\": \n{}\n\n", e);
-            std::process::exit(1);
-        });
-        println!("🏃🏻 Loading index page ...");
-        let index = IndexPage::load(self.config.get_page_index_path()).unwrap_or_else(|e| {
-            println!("\n💥 Failed to load \"pages/index.md\" page: \n{}\n\n", e);
-            std::process::exit(1);
-        });
-        self.posts = posts;
-        self.index = index;
+    /**
+     * Build into the output dir of the config.
+     */
+    pub fn with_config(config: Config) -> Self {
+        let output = FsOutput::new(&config);
+        Self::with_output(config, output)
+    }
+
+    /**
+     * Build into any output sink, eg. a `MemoryOutput` for the dev server.
+     */
+    pub fn with_output(config: Config, output: impl OutputSink + 'static) -> Self {
+        Self {
+            config,
+            index: IndexPage::default(),
+            posts: Posts::default(),
+            not_found: None,
+            quiet: false,
+            templates: Templates::default(),
+            cache: BuildCache::default(),
+            output: Box::new(output),
+            cancel: Arc::default(),
+            rendered: Vec::new(),
+            built_at: HashMap::new(),
+        }
+    }
+
+    /**
+     * Switch to a reloaded config, takes effect on the next build.
+     */
+    pub fn set_config(&mut self, config: Config) {
+        self.output.set_config(&config);
+        self.config = config;
+    }
+
+    /**
+     * A flag which cancels the running build when set, the build fails
+     * with `Error::Cancelled` and leaves the output as it was. It stays set
+     * until it is cleared, builds started meanwhile are cancelled too.
+     */
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancel.clone()
+    }
+
+    /**
+     * When the page of the post was last published, `None` when it was
+     * not rendered since the builder was created, eg. found in the cache.
+     */
+    pub fn built_at(&self, post: &Post) -> Option<DateTime<Local>> {
+        self.built_at
+            .get(&post.path.with_extension("html"))
+            .copied()
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match self.cancel.load(Ordering::SeqCst) {
+            true => Err(Error::Cancelled),
+            false => Ok(()),
+        }
+    }
+
+    /**
+     * Load all pages, errors of every file are collected instead of
+     * stopping at the first one. Posts which didn't change since the
+     * previous load are not parsed again.
+     */
+    pub fn load(&mut self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        match self.load_templates() {
+            Ok(templates) => self.templates = templates,
+            Err(e) => errors.push(e),
+        }
+
+        self.progress("🏃🏻 Loading posts ...");
+        match Posts::reload(self.config.get_page_posts_path(), &self.posts) {
+            Ok(posts) => self.posts = posts,
+            Err(e) => errors.push(e),
+        }
+
+        self.progress("🏃🏻 Loading index page ...");
+        match IndexPage::load(self.config.get_page_index_path()) {
+            Ok(index) => self.index = index,
+            Err(e) => errors.push(e),
+        }
+
+        let not_found_path = self.config.get_page_not_found_path();
+        self.not_found = None;
+        if not_found_path.exists() {
+            self.progress("🏃🏻 Loading 404 page ...");
+            match IndexPage::load(&not_found_path) {
+                Ok(page) => self.not_found = Some(page),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        Error::collect(errors)
+    }
+
+    fn load_templates(&self) -> Result<Templates> {
+        self.progress("🏃🏻 Loading templates ...");
+        let templates = Templates::load(&self.config.template_dir)?;
+        self.progress(&format!("\t- {} Tera templates loaded", templates.count()));
+        Ok(templates)
+    }
+
+    fn progress(&self, message: &str) {
+        if !self.quiet {
+            println!("{}", message);
+        }
     }
 
     pub fn create_page(&mut self, name: &str) -> Result<()> {
         let path = self.config.get_page_posts_path().join(name);
         if path.exists() {
-            bail!("Page already exists.");
-        }
-        let mut file = fs::File::create(&path)?;
-        let title = name.to_string().replace(".md", "");
-        let date = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-        let content = format!(
-            r#"---
-title: {title}
-date: {date}
-tags: 
-    - draft
----
-
-# Write your post here.
-
-"#
-        );
-        file.write_all(content.as_bytes())?;
+            return Err(Error::io(
+                path,
+                io::Error::new(io::ErrorKind::AlreadyExists, "Page already exists."),
+            ));
+        }
+        let front_matter = FrontMatter {
+            title: name.to_string().replace(".md", ""),
+            tags: Some(vec!["draft".to_string()]),
+            ..FrontMatter::default()
+        };
+        let content = front_matter.to_markdown(&path, "# Write your post here.\n")?;
+        fs::write(&path, content).with_path(&path)?;
         println!("✅ Create \"{}\" success.", &path.display().to_string());
         Ok(())
     }
 
     pub fn rebuild(&mut self) -> Result<()> {
-        self.load();
+        self.load()?;
+        self.write_site(false)
+    }
 
-        fs::remove_dir_all(&self.config.output_dir)?;
-        fs::create_dir_all(&self.config.output_dir)?;
-        self.build_posts()?;
-        self.build_index()?;
-        self.build_statics()?;
-        println!("✅ Build success.");
-        println!();
+    pub fn build(&mut self) -> Result<()> {
+        self.load()?;
+        self.write_site(true)
+    }
 
-        Ok(())
+    /**
+     * Reload the templates and rebuild, pages are not reloaded.
+     */
+    pub fn rebuild_templates(&mut self) -> Result<()> {
+        self.templates = self.load_templates()?;
+        self.write_site(false)
     }
 
-    pub fn build(&mut self) -> Result<()> {
-        self.load();
-        // if exists output dir, remove it
-        if fs::metadata(&self.config.output_dir).is_ok() {
-            fs::remove_dir_all(&self.config.output_dir)?;
+    /**
+     * Copy one changed static file straight into the output dir, or remove
+     * its output when the file is gone, without a full build.
+     * Returns the output path relative to the output dir, `None` when the
+     * file is not a static file of the site.
+     */
+    pub fn update_static(&mut self, src: &Path) -> Result<Option<PathBuf>> {
+        let output = match self.get_static_output_path(src) {
+            Some(output) => output?,
+            None => return Ok(None),
+        };
+        let rel = output
+            .strip_prefix(&self.config.output_dir)
+            .unwrap_or(&output);
+        if src.is_file() {
+            let content = fs::read(src).with_path(src)?;
+            self.output.write(rel, &content)?;
+            self.cache.record(rel, cache::fingerprint(&[&content]));
+        } else {
+            self.output.remove(rel)?;
+            self.cache.remove(rel);
         }
-        fs::create_dir_all(&self.config.output_dir)?;
+        self.output.save_cache(&self.cache)?;
+        Ok(Some(rel.to_path_buf()))
+    }
 
-        println!("🏃🏻 Building post pages...");
-        self.build_posts()?;
-        println!("\t- {} post pages built.", self.posts.len());
+    fn get_static_output_path(&self, src: &Path) -> Option<Result<PathBuf>> {
+        if src.starts_with(self.config.get_page_image_path()) {
+            Some(self.config.get_output_image_path(src))
+        } else if src.starts_with(self.config.static_dir.join("assets")) {
+            Some(self.config.get_output_assets_path(src))
+        } else if src.starts_with(self.config.static_dir.join("favicon")) {
+            Some(self.config.get_output_favicon_path(src))
+        } else {
+            None
+        }
+    }
 
-        println!("🏃🏻 Building index page...");
+    /**
+     * Build into the output sink, which publishes the site only when the
+     * whole build succeeded. A failed build leaves the previous output
+     * untouched.
+     */
+    fn write_site(&mut self, verbose: bool) -> Result<()> {
+        self.rendered.clear();
+        self.cache = self.output.begin()?;
+        if let Err(e) = self
+            .build_pages(verbose)
+            .and_then(|_| self.remove_stale_outputs())
+        {
+            self.output.discard();
+            return Err(e);
+        }
+        self.output.commit()?;
+        let now = Local::now();
+        for output in self.rendered.drain(..) {
+            self.built_at.insert(output, now);
+        }
+        self.output.save_cache(&self.cache)?;
+        self.progress("✅ Build success.\n");
+        Ok(())
+    }
+
+    fn build_pages(&mut self, verbose: bool) -> Result<()> {
+        self.check_cancelled()?;
+        if verbose {
+            println!("🏃🏻 Building post pages...");
+        }
+        let built = self.build_posts()?;
+        if verbose {
+            println!(
+                "\t- {} post pages built, {} unchanged.",
+                built,
+                self.posts.len() - built
+            );
+            println!("🏃🏻 Building index page...");
+        }
+        self.check_cancelled()?;
         self.build_index()?;
 
-        println!("🏃🏻 Copying static files...");
-        self.build_statics()?;
-        println!("✅ Build success.");
-        println!();
+        if verbose {
+            println!("🏃🏻 Building 404 page...");
+        }
+        self.build_not_found()?;
+
+        if verbose {
+            println!("🏃🏻 Copying static files...");
+        }
+        self.check_cancelled()?;
+        let copied = self.build_statics()?;
+        self.check_cancelled()?;
+        if verbose {
+            println!("\t- {} static files copied.", copied);
+        }
         Ok(())
     }
 
-    fn build_posts(&mut self) -> Result<()> {
-        let output = self.config.get_output_posts_path();
-        fs::create_dir_all(output)?;
+    /**
+     * Remove outputs of previous builds which are no longer generated.
+     */
+    fn remove_stale_outputs(&mut self) -> Result<()> {
+        for output in self.cache.finish() {
+            self.output.remove(&output)?;
+        }
+        Ok(())
+    }
 
+    /**
+     * Post pages are rendered in parallel, only stale pages are rendered.
+     * Errors of all failed pages are reported together.
+     */
+    fn build_posts(&mut self) -> Result<usize> {
+        let mut stale = Vec::new();
         for post in self.posts.as_ref() {
-            let rendered = templates::render_template(POST_TEMPLATE, post)?;
-            let path = post.path.with_extension("html");
-            let output = self.config.output_dir.join(path);
-
-            std::fs::write(output, rendered)?;
+            let output = post.path.with_extension("html");
+            let fingerprint = page_fingerprint(&self.templates, POST_TEMPLATE, post)?;
+            if !(self.cache.is_fresh(&output, &fingerprint) && self.output.exists(&output)) {
+                stale.push((post, output, fingerprint));
+            }
         }
 
-        self.copy_pages_image()?;
+        let sink = &*self.output;
+        let page_dir = &self.config.page_dir;
+        let templates = &self.templates;
+        let cancel = &self.cancel;
+        let results = stale
+            .par_iter()
+            .map(|(post, output, _)| {
+                if cancel.load(Ordering::SeqCst) {
+                    return Err(Error::Cancelled);
+                }
+                let source = page_dir.join(&post.path);
+                let rendered = templates.render(POST_TEMPLATE, post, Some(&source))?;
+                sink.write(output, rendered.as_bytes())
+            })
+            .collect::<Vec<_>>();
+
+        self.check_cancelled()?;
+        let built = stale.len();
+        let mut errors = Vec::new();
+        for ((_, output, fingerprint), result) in stale.into_iter().zip(results) {
+            match result {
+                Ok(()) => {
+                    self.cache.record(&output, fingerprint);
+                    self.rendered.push(output);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        Error::collect(errors)?;
 
-        Ok(())
+        Ok(built)
     }
 
-    fn copy_pages_image(&mut self) -> Result<()> {
-        let src = self.config.get_page_image_path();
-        let dst = &self.config.get_output_image_path(&src);
-        utils::copy_files(src.as_path(), dst.as_path())?;
-        Ok(())
+    fn copy_pages_image(&mut self) -> Result<usize> {
+        let mut copied = 0;
+        for src in utils::get_files_by_walkdir(self.config.get_page_image_path()) {
+            let output = self.config.get_output_image_path(&src)?;
+            if self.copy_static(&src, output)? {
+                copied += 1;
+            }
+        }
+        Ok(copied)
     }
 
     fn build_index(&mut self) -> Result<()> {
         let post_index = self.posts.get_post_index();
         self.index.set_post_index(post_index);
-        let rendered = templates::render_template(INDEX_TEMPLATE, &self.index)?;
-        let output = self.config.output_dir.join("index.html");
-        std::fs::write(output, rendered)?;
+        write_page(
+            &mut self.cache,
+            &*self.output,
+            &self.templates,
+            INDEX_TEMPLATE,
+            &self.index,
+            Path::new("index.html"),
+            &self.config.get_page_index_path(),
+        )?;
 
         Ok(())
     }
 
-    fn build_statics(&mut self) -> Result<()> {
-        self.build_assets()?;
-        self.build_favicon()?;
+    /**
+     * 404 page: rendered with the `404.html` template if there is one,
+     * otherwise `pages/404.md` is rendered with the index template.
+     */
+    fn build_not_found(&mut self) -> Result<()> {
+        let template = if self.templates.has_template(NOT_FOUND_TEMPLATE) {
+            NOT_FOUND_TEMPLATE
+        } else if self.not_found.is_some() {
+            INDEX_TEMPLATE
+        } else {
+            return Ok(());
+        };
+        let post_index = self.posts.get_post_index();
+        let page = self.not_found.get_or_insert_with(|| {
+            IndexPage::new("404 Not Found".to_string(), String::new(), None)
+        });
+        page.set_post_index(post_index);
+        write_page(
+            &mut self.cache,
+            &*self.output,
+            &self.templates,
+            template,
+            page,
+            Path::new(NOT_FOUND_PAGE),
+            &self.config.get_page_not_found_path(),
+        )?;
+
         Ok(())
     }
 
-    fn build_assets(&mut self) -> Result<()> {
-        let src = self.config.static_dir.join("assets");
-        let dst = self.config.get_output_assets_path(&src);
-        utils::copy_files(src.as_path(), dst.as_path()).unwrap();
+    fn build_statics(&mut self) -> Result<usize> {
+        let images = self.copy_pages_image()?;
+        let assets = self.build_assets()?;
+        let favicon = self.build_favicon()?;
+        Ok(images + assets + favicon)
+    }
 
-        Ok(())
+    fn build_assets(&mut self) -> Result<usize> {
+        let mut copied = 0;
+        for src in utils::get_files_by_walkdir(self.config.static_dir.join("assets")) {
+            let output = self.config.get_output_assets_path(&src)?;
+            if self.copy_static(&src, output)? {
+                copied += 1;
+            }
+        }
+        Ok(copied)
     }
 
-    fn build_favicon(&mut self) -> Result<()> {
+    fn build_favicon(&mut self) -> Result<usize> {
         // copy ico and favicon
-        let src = self.config.static_dir.join("favicon");
-        let dst = self.config.get_output_favicon_path(&src);
-        utils::copy_files(src.as_path(), dst.as_path())?;
-        Ok(())
+        let mut copied = 0;
+        for src in utils::get_files_by_walkdir(self.config.static_dir.join("favicon")) {
+            let output = self.config.get_output_favicon_path(&src)?;
+            if self.copy_static(&src, output)? {
+                copied += 1;
+            }
+        }
+        Ok(copied)
+    }
+
+    /**
+     * Copy a file to the output dir unless its content is unchanged,
+     * returns whether the file was copied.
+     */
+    fn copy_static(&mut self, src: &Path, output: PathBuf) -> Result<bool> {
+        let content = fs::read(src).with_path(src)?;
+        let fingerprint = cache::fingerprint(&[&content]);
+        let output = output
+            .strip_prefix(&self.config.output_dir)
+            .map(Path::to_path_buf)
+            .unwrap_or(output);
+        if self.cache.is_fresh(&output, &fingerprint) && self.output.exists(&output) {
+            return Ok(false);
+        }
+        self.output.write(&output, &content)?;
+        self.cache.record(&output, fingerprint);
+        Ok(true)
     }
 }
 
+/**
+ * Render a page into the output dir unless its output is up to date,
+ * returns whether the page was rendered.
+ * The fingerprint covers the templates and the whole render context,
+ * which is everything the rendered page depends on.
+ */
+fn write_page(
+    cache: &mut BuildCache,
+    sink: &dyn OutputSink,
+    templates: &Templates,
+    template_name: &str,
+    data: &impl Serialize,
+    output: &Path,
+    source: &Path,
+) -> Result<bool> {
+    let fingerprint = page_fingerprint(templates, template_name, data)?;
+    if cache.is_fresh(output, &fingerprint) && sink.exists(output) {
+        return Ok(false);
+    }
+    let rendered = templates.render(template_name, data, Some(source))?;
+    sink.write(output, rendered.as_bytes())?;
+    cache.record(output, fingerprint);
+    Ok(true)
+}
+
+fn page_fingerprint(
+    templates: &Templates,
+    template_name: &str,
+    data: &impl Serialize,
+) -> Result<String> {
+    let context = serde_json::to_vec(data)?;
+    Ok(cache::fingerprint(&[
+        templates.fingerprint().as_bytes(),
+        template_name.as_bytes(),
+        &context,
+    ]))
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -172,6 +518,55 @@ mod tests {
     fn test_build() {
         let mut site = SiteBuilder::new();
         assert!(site.build().is_ok());
+        let not_found = fs::read_to_string(site.config.get_output_not_found_path()).unwrap();
+        assert!(not_found.contains("404 Not Found"));
+        assert!(not_found.contains("/posts/test.html"));
+    }
+
+    #[test]
+    fn test_update_static() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut site = SiteBuilder::with_config(Config {
+            page_dir: dir.path().join("pages"),
+            static_dir: dir.path().join("static"),
+            output_dir: dir.path().join("dist"),
+            cache_dir: dir.path().join(".onepage"),
+            ..Config::default()
+        });
+        let style = dir.path().join("static/assets/style.css");
+        fs::create_dir_all(style.parent().unwrap()).unwrap();
+        fs::write(&style, "body {}").unwrap();
+
+        assert_eq!(
+            site.update_static(&style).unwrap(),
+            Some(PathBuf::from("assets/style.css"))
+        );
+        let output = dir.path().join("dist/assets/style.css");
+        assert_eq!(fs::read_to_string(&output).unwrap(), "body {}");
+
+        fs::remove_file(&style).unwrap();
+        assert!(site.update_static(&style).unwrap().is_some());
+        assert!(!output.exists());
+
+        assert!(site
+            .update_static(&dir.path().join("static/other.txt"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_cancel_build() {
+        let output = crate::MemoryOutput::new();
+        let files = output.files();
+        let mut site = SiteBuilder::with_output(Config::default(), output);
+        site.cancel_flag().store(true, Ordering::SeqCst);
+        assert!(matches!(site.build(), Err(Error::Cancelled)));
+        // nothing of a cancelled build is published
+        assert!(files.read().unwrap().is_empty());
+
+        site.cancel_flag().store(false, Ordering::SeqCst);
+        assert!(site.build().is_ok());
+        assert!(files.read().unwrap().contains_key(Path::new("index.html")));
     }
 
     #[test]
@@ -181,9 +576,9 @@ mod tests {
         assert!(site.create_page("new.md").is_ok());
         let path = Path::new("pages/posts/new.md");
         assert!(path.exists());
-        let content = std::fs::read_to_string(&path).unwrap();
+        let content = std::fs::read_to_string(path).unwrap();
         assert!(content.contains("new"));
         assert!(content.contains("Write your post here."));
-        fs::remove_file(&path).unwrap();
+        fs::remove_file(path).unwrap();
     }
 }
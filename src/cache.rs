@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{IoResultExt, Result};
+
+/**
+ * Fingerprints of every file written to the output dir, keyed by the path
+ * relative to the output dir.
+ * A fingerprint is the hash of all inputs of an output file (source content,
+ * templates, render context), so an output is only rewritten when one of
+ * them changes.
+ */
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BuildCache {
+    outputs: BTreeMap<PathBuf, String>,
+    // outputs produced by the current build
+    #[serde(skip)]
+    seen: BTreeSet<PathBuf>,
+}
+
+impl BuildCache {
+    /**
+     * Load the cache from disk, `None` if it is missing or unreadable.
+     */
+    pub fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let content = fs::read(path).ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_path(parent)?;
+        }
+        fs::write(path, serde_json::to_vec(self)?).with_path(path)?;
+        Ok(())
+    }
+
+    /**
+     * Check whether the output was built with this fingerprint,
+     * and mark it as produced by the current build.
+     */
+    pub fn is_fresh(&mut self, output: &Path, fingerprint: &str) -> bool {
+        self.seen.insert(output.to_path_buf());
+        self.outputs.get(output).map(String::as_str) == Some(fingerprint)
+    }
+
+    pub fn record(&mut self, output: &Path, fingerprint: String) {
+        self.seen.insert(output.to_path_buf());
+        self.outputs.insert(output.to_path_buf(), fingerprint);
+    }
+
+    /**
+     * Outputs of the previous builds.
+     */
+    pub fn outputs(&self) -> impl Iterator<Item = &Path> {
+        self.outputs.keys().map(PathBuf::as_path)
+    }
+
+    pub fn remove(&mut self, output: &Path) {
+        self.seen.remove(output);
+        self.outputs.remove(output);
+    }
+
+    /**
+     * Finish the current build, returns outputs of previous builds
+     * which were not produced this time.
+     */
+    pub fn finish(&mut self) -> Vec<PathBuf> {
+        let seen = std::mem::take(&mut self.seen);
+        let stale = self
+            .outputs
+            .keys()
+            .filter(|output| !seen.contains(*output))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.outputs.retain(|output, _| seen.contains(output));
+        stale
+    }
+}
+
+/**
+ * sha256 hex digest of all parts
+ */
+pub fn fingerprint(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint(&[b"a", b"b"]), fingerprint(&[b"a", b"b"]));
+        assert_ne!(fingerprint(&[b"ab", b""]), fingerprint(&[b"a", b"b"]));
+        assert_eq!(fingerprint(&[]).len(), 64);
+    }
+
+    #[test]
+    fn test_stale_outputs() {
+        let mut cache = BuildCache::default();
+        cache.record(Path::new("lib.rs"), "1".to_string());
+        cache.record(Path::new("main.rs"), "1".to_string());
+        assert!(cache.finish().is_empty());
+
+        assert!(cache.is_fresh(Path::new("lib.rs"), "1"));
+        assert!(!cache.is_fresh(Path::new("lib.rs"), "2"));
+        assert!(!cache.is_fresh(Path::new("missing.rs"), "1"));
+        assert_eq!(cache.finish(), vec![PathBuf::from("main.rs")]);
+        assert!(cache.outputs.contains_key(Path::new("lib.rs")));
+        assert!(!cache.outputs.contains_key(Path::new("main.rs")));
+    }
+}
@@ -1,27 +1,111 @@
-use crate::constants::*;
-use std::path::{Path, PathBuf};
+use crate::{
+    constants::*,
+    error::{Error, IoResultExt, Result},
+};
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    // site root, the output dir must be inside it, relative dirs are checked against it
+    pub root: PathBuf,
     // markdown file path
     pub page_dir: PathBuf,
     // static file path, include css, js, img..
     pub static_dir: PathBuf,
+    // tera templates path
+    pub template_dir: PathBuf,
     // output file path
     pub output_dir: PathBuf,
+    // build cache path
+    pub cache_dir: PathBuf,
+    // files in the output dir which are not generated, and survive builds
+    pub keep: Vec<PathBuf>,
+    // gui editor opening sources from the dev server, `$EDITOR` when unset
+    pub editor_command: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            root: PathBuf::from(BASE_PATH),
             page_dir: PathBuf::from(PAGE_DIR),
             static_dir: PathBuf::from(STATIC_DIR),
+            template_dir: PathBuf::from(TEMPLATE_DIR),
             output_dir: PathBuf::from(OUTPUT_DIR),
+            cache_dir: PathBuf::from(CACHE_DIR),
+            keep: vec![PathBuf::from(".git"), PathBuf::from("CNAME")],
+            editor_command: None,
         }
     }
 }
 
 impl Config {
+    /**
+     * Load config from a toml file, default config if the file doesn't exist.
+     */
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path).with_path(path)?;
+        toml::from_str(&content).map_err(|e| Error::Config {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })
+    }
+
+    /**
+     * Refuse to use an output dir which can't be safely deleted:
+     * the site root itself, a dir containing the sources, or a dir
+     * outside the site root. Keep list entries must stay inside the
+     * output dir, kept files replace what is at their path.
+     */
+    pub fn check_output_dir(&self) -> Result<()> {
+        let unsafe_output = |message: String| Error::Config {
+            path: self.output_dir.clone(),
+            message,
+        };
+        for path in &self.keep {
+            let inside = path.components().next().is_some()
+                && path
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)));
+            if !inside {
+                return Err(unsafe_output(format!(
+                    "keep entry \"{}\" is not a path inside the output dir, refusing to use it.",
+                    path.display()
+                )));
+            }
+        }
+        let root = fs::canonicalize(&self.root).with_path(&self.root)?;
+        let output = canonicalize_lossy(&root, &self.output_dir)?;
+        if output == root {
+            return Err(unsafe_output(
+                "output dir is the site root, refusing to delete it.".to_string(),
+            ));
+        }
+        if !output.starts_with(&root) {
+            return Err(unsafe_output(
+                "output dir is outside the site root, refusing to delete it.".to_string(),
+            ));
+        }
+        for dir in [&self.page_dir, &self.static_dir, &self.template_dir] {
+            if canonicalize_lossy(&root, dir)?.starts_with(&output) {
+                return Err(unsafe_output(format!(
+                    "output dir contains \"{}\", refusing to delete it.",
+                    dir.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_page_posts_path(&self) -> PathBuf {
         self.page_dir.join(POSTS_DIR)
     }
@@ -34,6 +118,61 @@ impl Config {
         self.page_dir.join("index.md")
     }
 
+    pub fn get_page_not_found_path(&self) -> PathBuf {
+        self.page_dir.join("404.md")
+    }
+
+    pub fn get_output_not_found_path(&self) -> PathBuf {
+        self.output_dir.join(NOT_FOUND_PAGE)
+    }
+
+    /** staging path, a build is written here and then swapped in:
+     *  output:  /dist
+     *  staging: /.dist.staging
+     */
+    pub fn get_staging_output_path(&self) -> PathBuf {
+        self.get_sibling_output_path("staging")
+    }
+
+    /** backup path, the previous output is moved here during the swap:
+     *  output: /dist
+     *  backup: /.dist.backup
+     */
+    pub fn get_backup_output_path(&self) -> PathBuf {
+        self.get_sibling_output_path("backup")
+    }
+
+    fn get_sibling_output_path(&self, suffix: &str) -> PathBuf {
+        let name = self
+            .output_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| OUTPUT_DIR.to_string());
+        self.output_dir
+            .with_file_name(format!(".{}.{}", name, suffix))
+    }
+
+    pub fn get_cache_path(&self) -> PathBuf {
+        self.cache_dir.join("cache")
+    }
+
+    /** self-signed certificate of `serve --https`:
+     *  cert: /.onepage/cert.pem
+     *  key:  /.onepage/key.pem
+     *  the hosts it was made for: /.onepage/cert.hosts
+     */
+    pub fn get_cert_path(&self) -> PathBuf {
+        self.cache_dir.join("cert.pem")
+    }
+
+    pub fn get_cert_key_path(&self) -> PathBuf {
+        self.cache_dir.join("key.pem")
+    }
+
+    pub fn get_cert_hosts_path(&self) -> PathBuf {
+        self.cache_dir.join("cert.hosts")
+    }
+
     pub fn get_page_image_path(&self) -> PathBuf {
         self.page_dir.join("image")
     }
@@ -42,32 +181,59 @@ impl Config {
      * input:  /pages/images/xxx.png
      * output: /dist/images/xxx.png
      */
-    pub fn get_output_image_path(&self, input: &Path) -> PathBuf {
-        let path = input.strip_prefix(&self.page_dir).unwrap();
-        self.output_dir.join(path)
+    pub fn get_output_image_path(&self, input: &Path) -> Result<PathBuf> {
+        let path = strip_dir(input, &self.page_dir)?;
+        Ok(self.output_dir.join(path))
     }
 
     /** assets path:
      *  input: /static/assets/xxx.css
      *  output: /dist/assets/xxx.css
      */
-    pub fn get_output_assets_path(&self, input: &Path) -> PathBuf {
-        let path = input.strip_prefix(&self.static_dir).unwrap();
-        self.output_dir.join(path)
+    pub fn get_output_assets_path(&self, input: &Path) -> Result<PathBuf> {
+        let path = strip_dir(input, &self.static_dir)?;
+        Ok(self.output_dir.join(path))
     }
 
     /** favicon path:
      *  input: /static/favicon/favicon.ico
      *  output: /dist/favicon.ico
      */
-    pub fn get_output_favicon_path(&self, input: &Path) -> PathBuf {
-        let path = input
-            .strip_prefix(&self.static_dir.join("favicon"))
-            .unwrap();
-        self.output_dir.join(path)
+    pub fn get_output_favicon_path(&self, input: &Path) -> Result<PathBuf> {
+        let path = strip_dir(input, &self.static_dir.join("favicon"))?;
+        Ok(self.output_dir.join(path))
     }
 }
 
+fn strip_dir<'a>(input: &'a Path, dir: &Path) -> Result<&'a Path> {
+    input.strip_prefix(dir).map_err(|_| Error::Config {
+        path: input.to_path_buf(),
+        message: format!("file is not inside \"{}\"", dir.display()),
+    })
+}
+
+/**
+ * canonicalize a path which may not exist yet, by canonicalizing
+ * its nearest existing ancestor. Relative paths are relative to root.
+ */
+fn canonicalize_lossy(root: &Path, path: &Path) -> Result<PathBuf> {
+    let path = root.join(path);
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    while fs::metadata(existing).is_err() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+    let mut canonical = fs::canonicalize(existing).with_path(existing)?;
+    canonical.extend(rest.into_iter().rev());
+    Ok(canonical)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,10 +243,94 @@ mod tests {
         let config = Config::default();
         assert_eq!(config.page_dir, PathBuf::from("pages"));
         assert_eq!(config.static_dir, PathBuf::from("static"));
+        assert_eq!(config.template_dir, PathBuf::from("templates"));
         assert_eq!(config.output_dir, PathBuf::from("dist"));
+        assert_eq!(config.cache_dir, PathBuf::from(".onepage"));
+        assert_eq!(
+            config.keep,
+            vec![PathBuf::from(".git"), PathBuf::from("CNAME")]
+        );
         println!("{:?}", config);
     }
 
+    #[test]
+    fn load_config() {
+        let config = Config::load("missing.toml").unwrap();
+        assert_eq!(config.output_dir, PathBuf::from("dist"));
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            file.path(),
+            "output_dir = \"public\"\nkeep = [\".nojekyll\"]\n",
+        )
+        .unwrap();
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.output_dir, PathBuf::from("public"));
+        assert_eq!(config.page_dir, PathBuf::from("pages"));
+        assert_eq!(config.keep, vec![PathBuf::from(".nojekyll")]);
+
+        fs::write(file.path(), "keep = \".git\"").unwrap();
+        assert!(Config::load(file.path()).is_err());
+    }
+
+    #[test]
+    fn check_output_dir() {
+        let mut config = Config::default();
+        assert!(config.check_output_dir().is_ok());
+
+        config.output_dir = PathBuf::from("dist/nested/site");
+        assert!(config.check_output_dir().is_ok());
+
+        for output_dir in [".", "./", "src/..", "..", "../dist", "/tmp"] {
+            config.output_dir = PathBuf::from(output_dir);
+            assert!(config.check_output_dir().is_err(), "{}", output_dir);
+        }
+
+        config.output_dir = PathBuf::from("out");
+        config.page_dir = PathBuf::from("out/pages");
+        assert!(config.check_output_dir().is_err());
+    }
+
+    #[test]
+    fn check_keep_paths() {
+        let mut config = Config {
+            keep: vec![PathBuf::from(".git"), PathBuf::from("assets/cdn")],
+            ..Config::default()
+        };
+        assert!(config.check_output_dir().is_ok());
+
+        for keep in ["../victim", "/", "/home/me", "a/../../b", "./CNAME", ""] {
+            config.keep = vec![PathBuf::from(keep)];
+            assert!(config.check_output_dir().is_err(), "{}", keep);
+        }
+    }
+
+    #[test]
+    fn site_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config {
+            root: dir.path().to_path_buf(),
+            page_dir: dir.path().join("pages"),
+            static_dir: dir.path().join("static"),
+            template_dir: dir.path().join("templates"),
+            output_dir: dir.path().join("dist"),
+            ..Config::default()
+        };
+        // outside of the current dir
+        assert!(config.check_output_dir().is_ok());
+
+        // relative dirs are in the site root, not in the current dir
+        config.output_dir = PathBuf::from("dist");
+        assert!(config.check_output_dir().is_ok());
+        config.page_dir = PathBuf::from("dist/pages");
+        assert!(config.check_output_dir().is_err());
+        config.page_dir = PathBuf::from("pages");
+        config.output_dir = PathBuf::from("../dist");
+        assert!(config.check_output_dir().is_err());
+        config.output_dir = dir.path().to_path_buf();
+        assert!(config.check_output_dir().is_err());
+    }
+
     #[test]
     fn get_path() {
         let config = Config::default();
@@ -90,23 +340,52 @@ mod tests {
             config.get_page_index_path(),
             PathBuf::from("pages/index.md")
         );
+        assert_eq!(
+            config.get_page_not_found_path(),
+            PathBuf::from("pages/404.md")
+        );
+        assert_eq!(
+            config.get_output_not_found_path(),
+            PathBuf::from("dist/404.html")
+        );
+        assert_eq!(config.get_cache_path(), PathBuf::from(".onepage/cache"));
+        assert_eq!(config.get_cert_path(), PathBuf::from(".onepage/cert.pem"));
+        assert_eq!(
+            config.get_cert_key_path(),
+            PathBuf::from(".onepage/key.pem")
+        );
+        assert_eq!(
+            config.get_staging_output_path(),
+            PathBuf::from(".dist.staging")
+        );
+        assert_eq!(
+            config.get_backup_output_path(),
+            PathBuf::from(".dist.backup")
+        );
 
         let image_path = config.get_page_image_path();
         assert_eq!(&image_path, &PathBuf::from("pages/image"));
 
         let image_file = image_path.join("xxx.png");
         assert_eq!(
-            config.get_output_image_path(&image_file),
+            config.get_output_image_path(&image_file).unwrap(),
             PathBuf::from("dist/image/xxx.png")
         );
 
         assert_eq!(
-            config.get_output_assets_path(&PathBuf::from("static/assets/123.css")),
+            config
+                .get_output_assets_path(&PathBuf::from("static/assets/123.css"))
+                .unwrap(),
             PathBuf::from("dist/assets/123.css")
         );
         assert_eq!(
-            config.get_output_favicon_path(&PathBuf::from("static/favicon/favicon.ico")),
+            config
+                .get_output_favicon_path(&PathBuf::from("static/favicon/favicon.ico"))
+                .unwrap(),
             PathBuf::from("dist/favicon.ico")
         );
+        assert!(config
+            .get_output_assets_path(&PathBuf::from("pages/assets/123.css"))
+            .is_err());
     }
 }
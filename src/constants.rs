@@ -3,7 +3,12 @@ pub const STATIC_DIR: &str = "static";
 pub const PAGE_DIR: &str = "pages";
 pub const POSTS_DIR: &str = "posts";
 pub const OUTPUT_DIR: &str = "dist";
+pub const TEMPLATE_DIR: &str = "templates";
+pub const CACHE_DIR: &str = ".onepage";
+pub const CONFIG_FILE: &str = "onepage.toml";
 pub const POST_TEMPLATE: &str = "post.html";
 pub const INDEX_TEMPLATE: &str = "index.html";
+pub const NOT_FOUND_TEMPLATE: &str = "404.html";
+pub const NOT_FOUND_PAGE: &str = "404.html";
 pub const STARTER_TEMPLATE_URL: &str =
     "https://github.com/hanpei/onepage-starter/archive/refs/heads/main.zip";
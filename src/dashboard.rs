@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    error::{Error, Result},
+    ErrorDetail, SiteBuilder,
+};
+
+// page of the dev server at `/__onepage`
+const DASHBOARD_TEMPLATE: &str = include_str!("dashboard.html");
+
+/**
+ * The dashboard as of the last build, replaced after every rebuild.
+ */
+pub type DashboardState = Arc<RwLock<Dashboard>>;
+
+/**
+ * Editorial overview of the site for the dev server, a snapshot of the
+ * builder taken after a build.
+ */
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Dashboard {
+    // newest first, like the index page
+    pub posts: Vec<PostStatus>,
+}
+
+/**
+ * One post on the dashboard.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PostStatus {
+    pub title: String,
+    pub url: String,
+    // source file, relative to the page dir
+    pub path: String,
+    pub date: String,
+    pub tags: Vec<String>,
+    // tagged `draft`
+    pub draft: bool,
+    pub words: usize,
+    // when the page was last published, empty when it came from the cache
+    pub built_at: Option<String>,
+}
+
+impl Dashboard {
+    pub fn of(site: &SiteBuilder) -> Self {
+        let mut posts = site
+            .posts
+            .as_ref()
+            .iter()
+            .map(|post| {
+                let tags = post.front_matter.tags.clone().unwrap_or_default();
+                PostStatus {
+                    title: post.title.clone(),
+                    url: post.url.clone(),
+                    path: post.path.display().to_string(),
+                    date: post.front_matter.date.clone(),
+                    draft: tags.iter().any(|tag| tag.eq_ignore_ascii_case("draft")),
+                    tags,
+                    words: post.word_count,
+                    built_at: site
+                        .built_at(post)
+                        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string()),
+                }
+            })
+            .collect::<Vec<_>>();
+        posts.sort_by(|a, b| b.date.cmp(&a.date));
+        Self { posts }
+    }
+
+    /**
+     * Render the dashboard page, `base` is the path the site is mounted at.
+     */
+    pub fn render(&self, base: &str, errors: &[ErrorDetail]) -> Result<String> {
+        let mut ctx = tera::Context::new();
+        ctx.insert("base", base);
+        ctx.insert("posts", &self.posts);
+        ctx.insert("errors", errors);
+        tera::Tera::one_off(DASHBOARD_TEMPLATE, &ctx, true)
+            .map_err(|e| Error::Server(format!("failed to render the dashboard: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, MemoryOutput};
+
+    #[test]
+    fn dashboard() {
+        let mut site = SiteBuilder::with_output(Config::default(), MemoryOutput::new());
+        site.build().unwrap();
+        let dashboard = Dashboard::of(&site);
+        let post = dashboard
+            .posts
+            .iter()
+            .find(|post| post.url == "/posts/test.html")
+            .unwrap();
+        assert_eq!(post.title, "Page for test");
+        assert!(post.draft);
+        assert_eq!(post.words, 4);
+        assert!(post.built_at.is_some());
+
+        let error = ErrorDetail {
+            file: Some("pages/posts/bad.md".to_string()),
+            line: Some(2),
+            column: Some(8),
+            message: "invalid <type>".to_string(),
+        };
+        let html = dashboard.render("/blog", &[error]).unwrap();
+        // autoescaped, browsers decode the entities
+        assert!(html.contains("href=\"&#x2F;blog&#x2F;posts&#x2F;test.html\""));
+        assert!(html.contains("pages&#x2F;posts&#x2F;bad.md:2:8"));
+        assert!(html.contains("invalid &lt;type&gt;"));
+    }
+}
@@ -0,0 +1,361 @@
+use axum::{
+    body::Body,
+    extract::{Extension, Query},
+    http::{HeaderMap, Request, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, IoResultExt, Result},
+    page::{Post, Posts},
+    server::mount_path,
+    templates::Templates,
+    Config, POST_TEMPLATE,
+};
+
+// web editor of `serve --editor`, its api is below it
+pub const EDITOR_PATH: &str = "/__editor";
+
+// header the editor sends its token in
+pub const EDITOR_TOKEN_HEADER: &str = "x-onepage-token";
+
+// page of the editor, the api is used by its script
+const EDITOR_TEMPLATE: &str = include_str!("editor.html");
+
+/**
+ * Web editor of the posts for `serve --editor`. Every request needs the
+ * token generated for this run, the editor is opened with a link holding it.
+ */
+#[derive(Debug, Clone)]
+pub struct Editor {
+    config: Config,
+    token: String,
+}
+
+/**
+ * A post in the list of the editor.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PostEntry {
+    // source file, relative to the page dir
+    pub path: String,
+    // the file name when the post doesn't parse
+    pub title: String,
+}
+
+/**
+ * Raw markdown of a post with its front matter.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostSource {
+    // relative to the page dir, eg. `posts/hello.md`
+    pub path: String,
+    pub content: String,
+}
+
+impl Editor {
+    pub fn new(config: &Config) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            token: generate_token()?,
+        })
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn is_authorized(&self, token: &str) -> bool {
+        tokens_match(token, &self.token)
+    }
+
+    /**
+     * The editor page, `base` is the path the site is mounted at.
+     */
+    pub fn render(&self, base: &str) -> Result<String> {
+        let mut ctx = tera::Context::new();
+        ctx.insert("base", base);
+        ctx.insert("token", &self.token);
+        tera::Tera::one_off(EDITOR_TEMPLATE, &ctx, true)
+            .map_err(|e| Error::Server(format!("failed to render the editor: {}", e)))
+    }
+
+    /**
+     * The posts on disk, broken ones included so they can be fixed.
+     */
+    pub fn posts(&self) -> Vec<PostEntry> {
+        let mut posts = Posts::files(self.config.get_page_posts_path())
+            .into_iter()
+            .map(|file| {
+                let title = fs::read_to_string(&file)
+                    .ok()
+                    .and_then(|raw| Post::read_front_matter(&raw, &file).ok())
+                    .map(|(front_matter, _)| front_matter.title)
+                    .unwrap_or_else(|| {
+                        file.file_stem()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_string()
+                    });
+                PostEntry {
+                    path: self.relative(&file),
+                    title,
+                }
+            })
+            .collect::<Vec<_>>();
+        posts.sort_by(|a, b| a.path.cmp(&b.path));
+        posts
+    }
+
+    pub fn read(&self, path: &str) -> Result<PostSource> {
+        let file = self.source_file(path)?;
+        Ok(PostSource {
+            path: path.to_string(),
+            content: fs::read_to_string(&file).with_path(&file)?,
+        })
+    }
+
+    /**
+     * Render unsaved markdown with the post template of the site, the
+     * templates are loaded for every preview so template edits show up.
+     */
+    pub fn preview(&self, source: &PostSource) -> Result<String> {
+        let file = self.source_file(&source.path)?;
+        let post = Post::parse_in(&self.config.page_dir, &file, &source.content)?;
+        let templates = Templates::load(&self.config.template_dir)?;
+        templates.render(POST_TEMPLATE, &post, Some(&file))
+    }
+
+    /**
+     * Write the post back to the page dir, the watcher rebuilds the site.
+     */
+    pub fn save(&self, source: &PostSource) -> Result<()> {
+        let file = self.source_file(&source.path)?;
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent).with_path(parent)?;
+        }
+        fs::write(&file, &source.content).with_path(&file)
+    }
+
+    /**
+     * The file of a post path from a request, only markdown files in the
+     * posts dir can be read or written.
+     */
+    fn source_file(&self, path: &str) -> Result<PathBuf> {
+        let relative = Path::new(path);
+        let file = self.config.page_dir.join(relative);
+        let valid = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+            && relative.extension().is_some_and(|ext| ext == "md")
+            && file.starts_with(self.config.get_page_posts_path());
+        match valid {
+            true => Ok(file),
+            false => Err(Error::Server(format!("\"{}\" is not a post", path))),
+        }
+    }
+
+    fn relative(&self, file: &Path) -> String {
+        file.strip_prefix(&self.config.page_dir)
+            .unwrap_or(file)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+}
+
+/**
+ * A random token of 128 bits from the random source of the os.
+ */
+pub fn generate_token() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| Error::Server(format!("failed to generate a token: {}", e)))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/**
+ * Compare in constant time, a token is all that guards the pages.
+ */
+pub fn tokens_match(token: &str, expected: &str) -> bool {
+    token.len() == expected.len()
+        && token
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/**
+ * The routes of the editor, merged into the dev routes of `serve --editor`.
+ */
+pub fn routes(editor: Editor) -> Router {
+    Router::new()
+        .route(EDITOR_PATH, get(editor_page))
+        .route("/__editor/posts", get(editor_posts))
+        .route("/__editor/post", get(editor_post))
+        .route("/__editor/preview", post(editor_preview))
+        .route("/__editor/save", post(editor_save))
+        .layer(Extension(editor))
+}
+
+#[derive(Debug, Deserialize)]
+struct EditorQuery {
+    token: Option<String>,
+    path: Option<String>,
+}
+
+// an error is its status with a message shown by the editor
+type EditorError = (StatusCode, String);
+
+type EditorResponse = std::result::Result<Response, EditorError>;
+
+/**
+ * The editor page, opened with the link holding the token.
+ */
+async fn editor_page(
+    Extension(editor): Extension<Editor>,
+    Query(query): Query<EditorQuery>,
+    req: Request<Body>,
+) -> EditorResponse {
+    if !query
+        .token
+        .is_some_and(|token| editor.is_authorized(&token))
+    {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "open the editor with the link printed by `onepage serve --editor`".to_string(),
+        ));
+    }
+    editor
+        .render(&mount_path(&req))
+        .map(|html| Html(html).into_response())
+        .map_err(editor_error)
+}
+
+async fn editor_posts(Extension(editor): Extension<Editor>, headers: HeaderMap) -> EditorResponse {
+    authorized_editor(&editor, &headers)?;
+    Ok(Json(editor.posts()).into_response())
+}
+
+async fn editor_post(
+    Extension(editor): Extension<Editor>,
+    headers: HeaderMap,
+    Query(query): Query<EditorQuery>,
+) -> EditorResponse {
+    authorized_editor(&editor, &headers)?;
+    editor
+        .read(&query.path.unwrap_or_default())
+        .map(|source| Json(source).into_response())
+        .map_err(editor_error)
+}
+
+async fn editor_preview(
+    Extension(editor): Extension<Editor>,
+    headers: HeaderMap,
+    Json(source): Json<PostSource>,
+) -> EditorResponse {
+    authorized_editor(&editor, &headers)?;
+    editor
+        .preview(&source)
+        .map(|html| Html(html).into_response())
+        .map_err(editor_error)
+}
+
+/**
+ * Save a post, the watcher rebuilds the site and reloads the browsers.
+ */
+async fn editor_save(
+    Extension(editor): Extension<Editor>,
+    headers: HeaderMap,
+    Json(source): Json<PostSource>,
+) -> EditorResponse {
+    authorized_editor(&editor, &headers)?;
+    editor.save(&source).map_err(editor_error)?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/**
+ * Check the request has the token of the editor.
+ */
+fn authorized_editor(editor: &Editor, headers: &HeaderMap) -> std::result::Result<(), EditorError> {
+    let token = headers
+        .get(EDITOR_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    match editor.is_authorized(token) {
+        true => Ok(()),
+        false => Err((StatusCode::UNAUTHORIZED, "invalid editor token".to_string())),
+    }
+}
+
+fn editor_error(error: Error) -> EditorError {
+    (StatusCode::UNPROCESSABLE_ENTITY, error.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn editor() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            page_dir: dir.path().join("pages"),
+            ..Config::default()
+        };
+        let posts_dir = config.get_page_posts_path();
+        fs::create_dir_all(&posts_dir).unwrap();
+        fs::write(
+            posts_dir.join("hello.md"),
+            "---\ntitle: Hello\ndate: 2022-01-01\n---\n# hi\n",
+        )
+        .unwrap();
+        fs::write(posts_dir.join("broken.md"), "no front matter").unwrap();
+
+        let editor = Editor::new(&config).unwrap();
+        assert_eq!(editor.token().len(), 32);
+        assert!(editor.is_authorized(editor.token()));
+        assert!(!editor.is_authorized("nope"));
+        assert_ne!(Editor::new(&config).unwrap().token(), editor.token());
+
+        let posts = editor.posts();
+        assert_eq!(
+            posts,
+            vec![
+                PostEntry {
+                    path: "posts/broken.md".to_string(),
+                    title: "broken".to_string()
+                },
+                PostEntry {
+                    path: "posts/hello.md".to_string(),
+                    title: "Hello".to_string()
+                },
+            ]
+        );
+
+        let mut source = editor.read("posts/hello.md").unwrap();
+        source.content.push_str("more\n");
+        let html = editor.preview(&source).unwrap();
+        assert!(html.contains("<p>more</p>"), "{}", html);
+        editor.save(&source).unwrap();
+        assert!(fs::read_to_string(posts_dir.join("hello.md"))
+            .unwrap()
+            .ends_with("more\n"));
+
+        for path in [
+            "../onepage.toml",
+            "posts/../../x.md",
+            "index.md",
+            "posts/a.txt",
+        ] {
+            assert!(editor.read(path).is_err(), "{}", path);
+        }
+    }
+}
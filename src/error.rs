@@ -0,0 +1,250 @@
+use std::{
+    fmt::{self, Display},
+    io,
+    path::{Path, PathBuf},
+};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/**
+ * Position in a source file, 1-based.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Template error in \"{template}\"{}{}", fmt_page(.page), fmt_location(.file, .span))]
+pub struct TemplateError {
+    pub template: String,
+    pub page: Option<PathBuf>,
+    // the template file and position the error points to, when it can be found
+    pub file: Option<PathBuf>,
+    pub span: Option<Span>,
+    // top level keys of the render context, to spot misspelled variables
+    pub context_keys: Vec<String>,
+    pub source: tera::Error,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid front matter in {}{}: {message}{}", .path.display(), fmt_span(.span), fmt_snippet(.snippet))]
+    FrontMatter {
+        path: PathBuf,
+        message: String,
+        span: Option<Span>,
+        // the offending yaml lines
+        snippet: Option<String>,
+    },
+
+    #[error(transparent)]
+    Template(Box<TemplateError>),
+
+    #[error("{}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+
+    #[error("Invalid config {}: {message}", .path.display())]
+    Config { path: PathBuf, message: String },
+
+    #[error("Failed to watch files")]
+    Watch(#[from] notify::Error),
+
+    #[error("Server error: {0}")]
+    Server(String),
+
+    #[error("Failed to initialize site: {0}")]
+    Init(String),
+
+    #[error("Failed to serialize page")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("Build cancelled")]
+    Cancelled,
+
+    // errors of every file which failed to build, so they can be fixed in one go
+    #[error("{}", fmt_errors(.0))]
+    Build(Vec<Error>),
+}
+
+impl Error {
+    pub fn io<P: Into<PathBuf>>(path: P, source: io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /**
+     * Combine the errors of many files, nested `Build` errors are flattened.
+     */
+    pub fn collect(errors: Vec<Error>) -> Result<()> {
+        let mut flat = Vec::new();
+        for error in errors {
+            match error {
+                Error::Build(errors) => flat.extend(errors),
+                error => flat.push(error),
+            }
+        }
+        match flat.len() {
+            0 => Ok(()),
+            1 => Err(flat.remove(0)),
+            _ => Err(Error::Build(flat)),
+        }
+    }
+
+    /**
+     * The error message followed by the messages of all its causes,
+     * and the render context of template errors.
+     */
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(cause) = source {
+            report.push_str(": ");
+            report.push_str(cause.to_string().trim());
+            source = cause.source();
+        }
+        if let Error::Template(error) = self {
+            if !error.context_keys.is_empty() {
+                let keys = error
+                    .context_keys
+                    .iter()
+                    .map(|key| format!("`{}`", key))
+                    .collect::<Vec<_>>();
+                report.push_str("\navailable context: ");
+                report.push_str(&keys.join(", "));
+            }
+        }
+        report
+    }
+
+    /**
+     * The file the error comes from, if any.
+     */
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::FrontMatter { path, .. }
+            | Error::Io { path, .. }
+            | Error::Config { path, .. } => Some(path),
+            Error::Template(error) => error.page.as_deref(),
+            _ => None,
+        }
+    }
+
+    /**
+     * The file and position the error points at. Template errors point at
+     * the template when it is known, otherwise at the page.
+     */
+    pub fn location(&self) -> Option<(&Path, Option<Span>)> {
+        match self {
+            Error::FrontMatter { path, span, .. } => Some((path, *span)),
+            Error::Template(error) => match &error.file {
+                Some(file) => Some((file, error.span)),
+                None => error.page.as_deref().map(|page| (page, None)),
+            },
+            Error::Io { path, .. } | Error::Config { path, .. } => Some((path, None)),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Attach the path of the file to io errors.
+ */
+pub(crate) trait IoResultExt<T> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T> {
+        self.map_err(|e| Error::io(path.as_ref(), e))
+    }
+}
+
+fn fmt_span(span: &Option<Span>) -> String {
+    span.map(|span| format!(":{}", span)).unwrap_or_default()
+}
+
+fn fmt_snippet(snippet: &Option<String>) -> String {
+    snippet
+        .as_ref()
+        .map(|snippet| format!("\n{}", snippet.trim_end()))
+        .unwrap_or_default()
+}
+
+fn fmt_location(file: &Option<PathBuf>, span: &Option<Span>) -> String {
+    file.as_ref()
+        .map(|file| format!(" at {}{}", file.display(), fmt_span(span)))
+        .unwrap_or_default()
+}
+
+fn fmt_page(page: &Option<PathBuf>) -> String {
+    page.as_ref()
+        .map(|page| format!(" for {}", page.display()))
+        .unwrap_or_default()
+}
+
+fn fmt_errors(errors: &[Error]) -> String {
+    let mut message = format!("💥 {} file(s) failed to build:\n", errors.len());
+    for error in errors {
+        message.push('\n');
+        for line in error.report().lines() {
+            message.push_str("    ");
+            message.push_str(line);
+            message.push('\n');
+        }
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_errors() {
+        assert!(Error::collect(vec![]).is_ok());
+
+        let single = Error::collect(vec![Error::Init("a".to_string())]).unwrap_err();
+        assert!(matches!(single, Error::Init(_)));
+
+        let nested = Error::Build(vec![
+            Error::io("pages/posts/a.md", io::ErrorKind::NotFound.into()),
+            Error::io("pages/posts/b.md", io::ErrorKind::NotFound.into()),
+        ]);
+        let front_matter = Error::FrontMatter {
+            path: PathBuf::from("pages/index.md"),
+            message: "missing field `title`".to_string(),
+            span: Some(Span { line: 2, column: 1 }),
+            snippet: None,
+        };
+        let error = Error::collect(vec![front_matter, nested]).unwrap_err();
+        match &error {
+            Error::Build(errors) => {
+                let paths = errors.iter().filter_map(Error::path).collect::<Vec<_>>();
+                assert_eq!(
+                    paths,
+                    vec![
+                        Path::new("pages/index.md"),
+                        Path::new("pages/posts/a.md"),
+                        Path::new("pages/posts/b.md")
+                    ]
+                );
+            }
+            _ => panic!("expected build error"),
+        }
+
+        let message = error.to_string();
+        assert!(message.contains("3 file(s) failed to build"));
+        assert!(message.contains("pages/index.md:2:1: missing field `title`"));
+        assert!(message.contains("pages/posts/a.md: entity not found"));
+    }
+}
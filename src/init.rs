@@ -1,16 +1,18 @@
-use anyhow::Result;
-use std::{fs, path::PathBuf};
+use std::{env, fs, path::PathBuf};
+
+use crate::error::{Error, IoResultExt, Result};
 
 pub fn init(root: &str, starter_template_url: &str) -> Result<()> {
-    let mut file = tempfile::tempfile()?;
+    let mut file = tempfile::tempfile().with_path(env::temp_dir())?;
     println!("Downloading starter template from {}", starter_template_url);
     reqwest::blocking::get(starter_template_url)
-        .unwrap()
-        .copy_to(&mut file)?;
-    let mut zip = zip::ZipArchive::new(file)?;
+        .and_then(|mut response| response.copy_to(&mut file))
+        .map_err(|e| Error::Init(format!("download {}: {}", starter_template_url, e)))?;
+    let mut zip = zip::ZipArchive::new(file).map_err(|e| Error::Init(e.to_string()))?;
 
-    zip.extract("./")?;
-    fs::rename(PathBuf::from("onepage-starter-main"), PathBuf::from(root))?;
+    zip.extract("./").map_err(|e| Error::Init(e.to_string()))?;
+    let starter = PathBuf::from("onepage-starter-main");
+    fs::rename(&starter, root).with_path(&starter)?;
     println!("Site initialized at {}", root);
     Ok(())
 }
@@ -1,15 +1,25 @@
+mod api;
 mod builder;
+mod cache;
 mod config;
 mod constants;
+mod dashboard;
+mod editor;
+mod error;
 mod init;
 mod markdown;
+mod output;
 mod page;
 mod server;
+mod sources;
 mod templates;
+mod tls;
 pub mod utils;
 
 pub use builder::*;
 pub use config::*;
 pub use constants::*;
+pub use error::*;
 pub use init::*;
+pub use output::*;
 pub use server::*;
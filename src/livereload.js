@@ -0,0 +1,142 @@
+// live reload client, injected into html pages by `onepage serve` only
+(function () {
+  // the path the site is mounted at, empty at the root
+  const base = document.currentScript.dataset.base;
+  const scheme = window.location.protocol === 'https:' ? 'wss://' : 'ws://';
+  const url = scheme + window.location.host + base + '/__ws';
+  let retries = 0;
+
+  function swapStylesheet(path) {
+    document.querySelectorAll('link[rel="stylesheet"]').forEach(function (link) {
+      const href = new URL(link.href);
+      if (href.pathname === base + path) {
+        href.searchParams.set('t', Date.now());
+        link.href = href.href;
+      }
+    });
+  }
+
+  function hideErrors() {
+    const overlay = document.getElementById('__onepage-overlay');
+    if (overlay) {
+      overlay.remove();
+    }
+  }
+
+  // build errors over the page, dismissed by the close button or Escape
+  function showErrors(errors) {
+    hideErrors();
+    const overlay = document.createElement('div');
+    overlay.id = '__onepage-overlay';
+    overlay.style.cssText =
+      'position:fixed;inset:0;z-index:2147483647;overflow:auto;padding:2rem;' +
+      'background:rgba(24,28,37,0.95);color:#e0e0e0;font:14px/1.5 monospace;';
+
+    const close = document.createElement('button');
+    close.textContent = '×';
+    close.title = 'Dismiss (Esc)';
+    close.style.cssText =
+      'position:absolute;top:1rem;right:1rem;width:auto;margin:0;padding:0 0.5rem;' +
+      'background:none;border:none;color:#e0e0e0;font-size:1.5rem;cursor:pointer;';
+    close.addEventListener('click', hideErrors);
+    overlay.appendChild(close);
+
+    const title = document.createElement('h2');
+    title.textContent = 'Build failed';
+    title.style.cssText = 'margin:0 0 1rem;color:#ff6b6b;font-family:monospace;';
+    overlay.appendChild(title);
+
+    errors.forEach(function (error) {
+      if (error.file) {
+        const location = document.createElement('div');
+        location.textContent =
+          error.file +
+          (error.line ? ':' + error.line : '') +
+          (error.column ? ':' + error.column : '');
+        location.style.cssText = 'color:#ffd166;margin-top:1rem;';
+        overlay.appendChild(location);
+      }
+      const message = document.createElement('pre');
+      message.textContent = error.message;
+      message.style.cssText =
+        'white-space:pre-wrap;margin:0.5rem 0;padding:0;background:none;color:inherit;';
+      overlay.appendChild(message);
+    });
+
+    document.body.appendChild(overlay);
+  }
+
+  document.addEventListener('keydown', function (event) {
+    if (event.key === 'Escape') {
+      hideErrors();
+    }
+  });
+
+  // opens the markdown of the page in the editor of the developer
+  function addOpenButton() {
+    if (window.location.pathname.startsWith(base + '/__')) {
+      return;
+    }
+    const button = document.createElement('button');
+    button.textContent = '✎';
+    button.title = 'Open the source in your editor';
+    button.style.cssText =
+      'position:fixed;right:1rem;bottom:1rem;z-index:2147483646;width:2.2rem;height:2.2rem;' +
+      'margin:0;padding:0;border:none;border-radius:50%;background:rgba(24,28,37,0.6);' +
+      'color:#fff;font:1rem/2.2rem sans-serif;cursor:pointer;';
+    button.addEventListener('click', function () {
+      fetch(base + '/__open', {
+        method: 'POST',
+        headers: { 'content-type': 'application/json' },
+        body: JSON.stringify({ path: window.location.pathname.slice(base.length) || '/' }),
+      }).then(function (response) {
+        if (!response.ok) {
+          response.text().then(function (text) {
+            button.title = text;
+            button.style.background = 'rgba(207,34,46,0.8)';
+          });
+        }
+      });
+    });
+    document.body.appendChild(button);
+  }
+
+  function handle(message) {
+    const page = window.location.pathname;
+    if (message.kind === 'error') {
+      showErrors(message.errors);
+    } else if (message.kind === 'css') {
+      // keeps the scroll position
+      hideErrors();
+      swapStylesheet(message.path);
+    } else if (message.kind === 'page') {
+      const isIndex = page === base || page === base + '/' || page === base + '/index.html';
+      const isDashboard = page === base + '/__onepage';
+      if (isIndex || isDashboard || page === base + message.path) {
+        window.location.reload();
+      }
+    } else {
+      window.location.reload();
+    }
+  }
+
+  function connect() {
+    const socket = new WebSocket(url);
+    socket.addEventListener('open', function () {
+      // the server was restarted, the page may be stale
+      if (retries > 0) {
+        window.location.reload();
+      }
+    });
+    socket.addEventListener('message', function (event) {
+      handle(JSON.parse(event.data));
+    });
+    socket.addEventListener('close', function () {
+      retries += 1;
+      setTimeout(connect, Math.min(500 * retries, 5000));
+    });
+  }
+
+  addOpenButton();
+  connect();
+})();
@@ -1,33 +1,100 @@
-use anyhow::Result;
-use clap::{crate_version, Arg, Command};
-use onepage::{init, SiteBuilder, SiteServer, STARTER_TEMPLATE_URL};
-
-fn main() -> Result<(), anyhow::Error> {
-    let matches = Command::new("onepage")
-        .author("hanpei")
-        .version(crate_version!())
-        .arg_required_else_help(true)
-        .subcommand_required(true)
-        .about("A simple static site generator")
-        .subcommand(
-            Command::new("init").about("Initialize the site").arg(
-                Arg::new("dir")
-                    .value_name("DIR")
-                    .default_value("./onepage")
-                    .help("The directory to initilize the site"),
-            ),
-        )
-        .subcommand(Command::new("build").about("Build the site"))
-        .subcommand(Command::new("serve").about("Serve the site"))
-        .subcommand(
-            Command::new("new").about("Create a new post page").arg(
-                Arg::new("name")
-                    .value_name("NAME")
-                    .required(true)
-                    .help("The name of the post page"),
-            ),
-        )
-        .get_matches();
+use anyhow::{Context, Result};
+use clap::{crate_version, Arg, ArgMatches, Command};
+use onepage::{
+    build_watch, init, Config, SiteBuilder, SiteServer, CONFIG_FILE, STARTER_TEMPLATE_URL,
+};
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", report(&e));
+        std::process::exit(1);
+    }
+}
+
+/**
+ * onepage errors are reported with their causes and the render context of
+ * template errors, which their `Debug` output leaves out.
+ */
+fn report(error: &anyhow::Error) -> String {
+    match error.downcast_ref::<onepage::Error>() {
+        Some(error) => error.report(),
+        None => format!("{:?}", error),
+    }
+}
+
+fn run() -> Result<()> {
+    let matches =
+        Command::new("onepage")
+            .author("hanpei")
+            .version(crate_version!())
+            .arg_required_else_help(true)
+            .subcommand_required(true)
+            .about("A simple static site generator")
+            .subcommand(
+                Command::new("init").about("Initialize the site").arg(
+                    Arg::new("dir")
+                        .value_name("DIR")
+                        .default_value("./onepage")
+                        .help("The directory to initilize the site"),
+                ),
+            )
+            .subcommand(
+                Command::new("build")
+                    .about("Build the site")
+                    .arg(jobs_arg())
+                    .arg(Arg::new("watch").short('w').long("watch").help(
+                        "Keep the output dir up to date, rebuilding on changes without serving it",
+                    )),
+            )
+            .subcommand(
+                Command::new("serve")
+                    .about("Serve the site")
+                    .arg(jobs_arg())
+                    .arg(
+                        Arg::new("host")
+                            .long("host")
+                            .value_name("HOST")
+                            .default_value("127.0.0.1")
+                            .help("The host to serve on, 0.0.0.0 to preview on the LAN"),
+                    )
+                    .arg(
+                        Arg::new("port")
+                            .short('p')
+                            .long("port")
+                            .value_name("PORT")
+                            .default_value("8080")
+                            .help(
+                                "The port to serve on, the next free port is used when it is busy",
+                            ),
+                    )
+                    .arg(
+                        Arg::new("open")
+                            .short('o')
+                            .long("open")
+                            .help("Open the site in the browser"),
+                    )
+                    .arg(Arg::new("https").long("https").help(
+                        "Serve over https with a self-signed certificate cached in .onepage/",
+                    ))
+                    .arg(Arg::new("static").long("static").help(
+                        "Serve the built site without building or watching, like a real host",
+                    ))
+                    .arg(Arg::new("editor").long("editor").help(
+                        "Serve a web editor of the posts at /__editor, opened with the link printed at start",
+                    ))
+                    .arg(Arg::new("api").long("api").help(
+                        "Serve a json api of the posts at /__api/posts, with the bearer token of ONEPAGE_API_TOKEN",
+                    )),
+            )
+            .subcommand(
+                Command::new("new").about("Create a new post page").arg(
+                    Arg::new("name")
+                        .value_name("NAME")
+                        .required(true)
+                        .help("The name of the post page"),
+                ),
+            )
+            .get_matches();
 
     match matches.subcommand() {
         Some(("init", arg_matches)) => {
@@ -35,15 +102,30 @@ fn main() -> Result<(), anyhow::Error> {
             init(dir, STARTER_TEMPLATE_URL)?;
             Ok(())
         }
-        Some(("build", _)) => {
-            let mut site = SiteBuilder::new();
+        Some(("build", arg_matches)) => {
+            set_jobs(arg_matches)?;
+            let mut site = SiteBuilder::with_config(Config::load(CONFIG_FILE)?);
+            if arg_matches.is_present("watch") {
+                build_watch(site)?;
+                return Ok(());
+            }
             site.build()?;
 
             Ok(())
         }
-        Some(("serve", _)) => {
+        Some(("serve", arg_matches)) => {
+            set_jobs(arg_matches)?;
             println!("Serve site");
-            SiteServer::new("127.0.0.1:8080").run()?;
+            let host = arg_matches.value_of("host").unwrap();
+            let port = arg_matches.value_of("port").unwrap();
+            SiteServer::new(&format!("{}:{}", host, port))?
+                .with_config(Config::load(CONFIG_FILE)?)
+                .with_open(arg_matches.is_present("open"))
+                .with_static(arg_matches.is_present("static"))
+                .with_https(arg_matches.is_present("https"))
+                .with_editor(arg_matches.is_present("editor"))
+                .with_api(arg_matches.is_present("api"))
+                .run()?;
             Ok(())
         }
         Some(("new", arg_matches)) => {
@@ -51,7 +133,7 @@ fn main() -> Result<(), anyhow::Error> {
             if !name.ends_with(".md") {
                 name.push_str(".md");
             }
-            let mut site = SiteBuilder::new();
+            let mut site = SiteBuilder::with_config(Config::load(CONFIG_FILE)?);
 
             site.create_page(&name)?;
 
@@ -60,3 +142,60 @@ fn main() -> Result<(), anyhow::Error> {
         _ => unreachable!(),
     }
 }
+
+fn jobs_arg() -> Arg<'static> {
+    Arg::new("jobs")
+        .short('j')
+        .long("jobs")
+        .value_name("N")
+        .takes_value(true)
+        .help("Number of threads to render pages with, defaults to the number of cores")
+}
+
+fn set_jobs(arg_matches: &ArgMatches) -> Result<()> {
+    if let Some(jobs) = arg_matches.value_of("jobs") {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(
+                jobs.parse()
+                    .with_context(|| format!("Invalid --jobs value \"{}\"", jobs))?,
+            )
+            .build_global()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn report_template_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            root: dir.path().to_path_buf(),
+            page_dir: dir.path().join("pages"),
+            static_dir: dir.path().join("static"),
+            template_dir: dir.path().join("templates"),
+            output_dir: dir.path().join("dist"),
+            cache_dir: dir.path().join(".onepage"),
+            ..Config::default()
+        };
+        fs::create_dir_all(config.get_page_posts_path()).unwrap();
+        fs::create_dir_all(&config.template_dir).unwrap();
+        fs::write(config.get_page_index_path(), "# Home\n").unwrap();
+        fs::write(config.template_dir.join("index.html"), "{{ nope }}").unwrap();
+        fs::write(config.template_dir.join("post.html"), "{{ content }}").unwrap();
+
+        let mut site = SiteBuilder::with_config(config);
+        site.quiet = true;
+        let error = anyhow::Error::from(site.build().unwrap_err());
+        let message = report(&error);
+        assert!(message.contains("Variable `nope` not found"), "{}", message);
+        assert!(message.contains("available context: `"), "{}", message);
+        assert!(!format!("{:?}", error).contains("available context"));
+
+        let error = anyhow::anyhow!("invalid value").context("Invalid --jobs value");
+        assert!(report(&error).contains("invalid value"));
+    }
+}
@@ -30,3 +30,34 @@ pub fn parse_md_to_html(markdown_input: &str) -> String {
     // Check that the output is what we expected.
     html_output
 }
+
+/**
+ * Words in the text of the markdown, without the markup.
+ * CJK characters count as one word each.
+ */
+pub fn count_words(markdown_input: &str) -> usize {
+    let mut text = String::new();
+    for event in Parser::new(markdown_input) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::End(_) | Event::SoftBreak | Event::HardBreak => text.push(' '),
+            _ => {}
+        }
+    }
+    text.split_whitespace()
+        .map(|word| {
+            let cjk = word.chars().filter(|c| is_cjk(*c)).count();
+            let other = word.chars().any(|c| c.is_alphanumeric() && !is_cjk(c));
+            cjk + usize::from(other)
+        })
+        .sum()
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}' // kana
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}' // han
+        | '\u{AC00}'..='\u{D7AF}' // hangul
+    )
+}
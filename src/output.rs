@@ -0,0 +1,399 @@
+use axum::body::Bytes;
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use crate::{
+    cache::BuildCache,
+    error::{Error, IoResultExt, Result},
+    utils, Config,
+};
+
+/**
+ * Where a build is written to. Paths are relative to the output dir.
+ * A build starts with `begin`, writes its files, and is published at once
+ * by `commit`, or dropped by `discard` when it fails. Writes outside of a
+ * build go straight to the published site.
+ */
+pub trait OutputSink: Debug + Send + Sync {
+    /**
+     * Start a build, returns the cache of the output it starts from.
+     */
+    fn begin(&mut self) -> Result<BuildCache>;
+
+    fn exists(&self, path: &Path) -> bool;
+
+    fn write(&self, path: &Path, content: &[u8]) -> Result<()>;
+
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    /**
+     * Publish the current build.
+     */
+    fn commit(&mut self) -> Result<()>;
+
+    fn discard(&mut self);
+
+    fn save_cache(&mut self, cache: &BuildCache) -> Result<()>;
+
+    /**
+     * Follow a reloaded config.
+     */
+    fn set_config(&mut self, _config: &Config) {}
+}
+
+/**
+ * Writes the site into the output dir. A build is written into a staging
+ * dir beside the output dir, which is swapped in only when the whole build
+ * succeeded, a failed build leaves the previous output untouched.
+ *
+ * The output dir is replaced rather than updated, it is a new dir with a new
+ * inode after every build. A bind mount of the output dir itself, or a
+ * process holding it open, keeps seeing the old build, use its parent.
+ */
+#[derive(Debug, Default)]
+pub struct FsOutput {
+    config: Config,
+    // dir the current build is written to
+    staging_dir: Option<PathBuf>,
+}
+
+impl FsOutput {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            staging_dir: None,
+        }
+    }
+
+    fn target(&self) -> &Path {
+        self.staging_dir
+            .as_deref()
+            .unwrap_or(&self.config.output_dir)
+    }
+}
+
+impl OutputSink for FsOutput {
+    /**
+     * Set up the staging dir. With a build cache it starts with the outputs
+     * recorded in it, so only changed pages need to be written, without a
+     * cache everything is built from scratch. Files onepage didn't write are
+     * never carried over, only the keep list survives a build.
+     */
+    fn begin(&mut self) -> Result<BuildCache> {
+        self.config.check_output_dir()?;
+        let staging_dir = self.config.get_staging_output_path();
+        if fs::metadata(&staging_dir).is_ok() {
+            fs::remove_dir_all(&staging_dir).with_path(&staging_dir)?;
+        }
+        let cache = match BuildCache::load(self.config.get_cache_path()) {
+            Some(cache) if self.config.output_dir.is_dir() => {
+                let keep = &self.config.keep;
+                let outputs = cache
+                    .outputs()
+                    .filter(|output| !keep.iter().any(|path| output.starts_with(path)));
+                utils::link_files(&self.config.output_dir, &staging_dir, outputs)?;
+                cache
+            }
+            _ => BuildCache::default(),
+        };
+        fs::create_dir_all(&staging_dir).with_path(&staging_dir)?;
+        self.staging_dir = Some(staging_dir);
+        Ok(cache)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.target().join(path).exists()
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+        let path = self.target().join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_path(parent)?;
+        }
+        // the staged file may be a hard link to the current output, don't write through it
+        if path.exists() {
+            fs::remove_file(&path).with_path(&path)?;
+        }
+        fs::write(&path, content).with_path(&path)?;
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        let path = self.target().join(path);
+        if path.is_file() {
+            fs::remove_file(&path).with_path(&path)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Swap the staging dir in place of the output dir.
+     * Files on the keep list are moved over from the previous output.
+     *
+     * On Linux both dirs are exchanged at once. Elsewhere, or on file systems
+     * which can't, the swap is not atomic: the output dir is renamed to a
+     * backup before the staging dir takes its place, and is missing in
+     * between.
+     */
+    fn commit(&mut self) -> Result<()> {
+        let staging_dir = match &self.staging_dir {
+            Some(staging_dir) => staging_dir.clone(),
+            None => return Ok(()),
+        };
+        let output_dir = &self.config.output_dir;
+        let keep = &self.config.keep;
+        if fs::metadata(output_dir).is_ok() {
+            if let Err(e) = move_kept(keep, output_dir, &staging_dir) {
+                move_kept(keep, &staging_dir, output_dir)?;
+                self.discard();
+                return Err(e);
+            }
+            match utils::exchange_dirs(&staging_dir, output_dir) {
+                // the previous output is in the staging dir now
+                Ok(true) => {
+                    self.discard();
+                    return Ok(());
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    move_kept(keep, &staging_dir, output_dir)?;
+                    let error = Error::io(output_dir, e);
+                    self.discard();
+                    return Err(error);
+                }
+            }
+        }
+        let backup_dir = self.config.get_backup_output_path();
+        if fs::metadata(&backup_dir).is_ok() {
+            fs::remove_dir_all(&backup_dir).with_path(&backup_dir)?;
+        }
+        if fs::metadata(output_dir).is_ok() {
+            fs::rename(output_dir, &backup_dir).with_path(output_dir)?;
+        }
+        if let Err(e) = fs::rename(&staging_dir, output_dir) {
+            // put the previous output back
+            if fs::metadata(&backup_dir).is_ok() {
+                move_kept(keep, &staging_dir, &backup_dir)?;
+                fs::rename(&backup_dir, output_dir).with_path(&backup_dir)?;
+            }
+            let error = Error::io(output_dir, e);
+            self.discard();
+            return Err(error);
+        }
+        self.staging_dir = None;
+        if fs::metadata(&backup_dir).is_ok() {
+            fs::remove_dir_all(&backup_dir).with_path(&backup_dir)?;
+        }
+        Ok(())
+    }
+
+    fn discard(&mut self) {
+        if let Some(staging_dir) = self.staging_dir.take() {
+            if fs::metadata(&staging_dir).is_ok() {
+                if let Err(e) = fs::remove_dir_all(&staging_dir) {
+                    println!("💥 Failed to remove \"{}\": {}", staging_dir.display(), e);
+                }
+            }
+        }
+    }
+
+    fn save_cache(&mut self, cache: &BuildCache) -> Result<()> {
+        cache.save(self.config.get_cache_path())
+    }
+
+    fn set_config(&mut self, config: &Config) {
+        self.config = config.clone();
+    }
+}
+
+/**
+ * Files of the site published by a `MemoryOutput`, keyed by the path
+ * relative to the site root.
+ */
+pub type MemoryFiles = Arc<RwLock<BTreeMap<PathBuf, Bytes>>>;
+
+/**
+ * Keeps the site in memory, for the dev server. A build is written into a
+ * copy of the published files, which replaces them at once on commit, so
+ * readers never see a half-written site. Nothing touches the disk, the
+ * build cache is kept in memory too.
+ */
+#[derive(Debug, Default)]
+pub struct MemoryOutput {
+    files: MemoryFiles,
+    staging: Mutex<Option<BTreeMap<PathBuf, Bytes>>>,
+    cache: BuildCache,
+}
+
+impl MemoryOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Handle to the published files, for serving them.
+     */
+    pub fn files(&self) -> MemoryFiles {
+        self.files.clone()
+    }
+}
+
+impl OutputSink for MemoryOutput {
+    fn begin(&mut self) -> Result<BuildCache> {
+        *self.staging.lock().unwrap() = Some(self.files.read().unwrap().clone());
+        Ok(self.cache.clone())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        match self.staging.lock().unwrap().as_ref() {
+            Some(staging) => staging.contains_key(path),
+            None => self.files.read().unwrap().contains_key(path),
+        }
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+        let content = Bytes::copy_from_slice(content);
+        match self.staging.lock().unwrap().as_mut() {
+            Some(staging) => staging.insert(path.to_path_buf(), content),
+            None => self
+                .files
+                .write()
+                .unwrap()
+                .insert(path.to_path_buf(), content),
+        };
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        match self.staging.lock().unwrap().as_mut() {
+            Some(staging) => staging.remove(path),
+            None => self.files.write().unwrap().remove(path),
+        };
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if let Some(staging) = self.staging.lock().unwrap().take() {
+            *self.files.write().unwrap() = staging;
+        }
+        Ok(())
+    }
+
+    fn discard(&mut self) {
+        self.staging.lock().unwrap().take();
+    }
+
+    fn save_cache(&mut self, cache: &BuildCache) -> Result<()> {
+        self.cache = cache.clone();
+        Ok(())
+    }
+}
+
+/**
+ * Move the keep list entries which exist in one dir to another dir.
+ */
+fn move_kept(keep: &[PathBuf], from: &Path, to: &Path) -> Result<()> {
+    for path in keep {
+        let src = from.join(path);
+        if fs::symlink_metadata(&src).is_err() {
+            continue;
+        }
+        let dst = to.join(path);
+        if let Ok(metadata) = fs::symlink_metadata(&dst) {
+            // kept files win over generated ones
+            if metadata.is_dir() {
+                fs::remove_dir_all(&dst).with_path(&dst)?;
+            } else {
+                fs::remove_file(&dst).with_path(&dst)?;
+            }
+        }
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent).with_path(parent)?;
+        }
+        fs::rename(&src, dst).with_path(&src)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_output() {
+        let mut output = MemoryOutput::new();
+        let files = output.files();
+        let page = Path::new("index.html");
+
+        output.begin().unwrap();
+        output.write(page, b"hello").unwrap();
+        assert!(output.exists(page));
+        // nothing is published before the commit
+        assert!(files.read().unwrap().is_empty());
+        output.commit().unwrap();
+        assert_eq!(files.read().unwrap()[page], Bytes::from("hello"));
+
+        output.begin().unwrap();
+        output.remove(page).unwrap();
+        output.discard();
+        assert!(output.exists(page));
+
+        // outside of a build writes are published at once
+        output.write(Path::new("style.css"), b"body {}").unwrap();
+        assert!(files.read().unwrap().contains_key(Path::new("style.css")));
+    }
+
+    #[test]
+    fn fs_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            root: dir.path().to_path_buf(),
+            page_dir: dir.path().join("pages"),
+            static_dir: dir.path().join("static"),
+            template_dir: dir.path().join("templates"),
+            output_dir: dir.path().join("dist"),
+            cache_dir: dir.path().join(".onepage"),
+            keep: vec![PathBuf::from("CNAME")],
+            ..Config::default()
+        };
+        let output_dir = &config.output_dir;
+        let read = |path: &str| fs::read_to_string(output_dir.join(path)).ok();
+        let page = Path::new("index.html");
+        let mut output = FsOutput::new(&config);
+
+        let mut cache = output.begin().unwrap();
+        output.write(page, b"v1").unwrap();
+        cache.record(page, "v1".to_string());
+        output.commit().unwrap();
+        output.save_cache(&cache).unwrap();
+        fs::write(output_dir.join("CNAME"), "example.com").unwrap();
+        fs::write(output_dir.join("extra.txt"), "not generated").unwrap();
+
+        // a failed build leaves the previous output untouched
+        output.begin().unwrap();
+        output.write(page, b"v2").unwrap();
+        output.write(Path::new("new.html"), b"new").unwrap();
+        output.discard();
+        assert_eq!(read("index.html").unwrap(), "v1");
+        assert_eq!(read("CNAME").unwrap(), "example.com");
+        assert_eq!(read("extra.txt").unwrap(), "not generated");
+        assert!(read("new.html").is_none());
+        assert!(!config.get_staging_output_path().exists());
+
+        // cached outputs and kept files are carried over, nothing else
+        output.begin().unwrap();
+        output.write(Path::new("new.html"), b"new").unwrap();
+        output.commit().unwrap();
+        assert_eq!(read("index.html").unwrap(), "v1");
+        assert_eq!(read("new.html").unwrap(), "new");
+        assert_eq!(read("CNAME").unwrap(), "example.com");
+        assert!(read("extra.txt").is_none());
+        assert!(!config.get_staging_output_path().exists());
+        assert!(!config.get_backup_output_path().exists());
+    }
+}
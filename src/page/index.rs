@@ -1,9 +1,12 @@
 use std::path::Path;
 
-use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use crate::{markdown::parse_md_to_html, LoadPage};
+use crate::{
+    error::{IoResultExt, Result},
+    markdown::parse_md_to_html,
+    LoadPage,
+};
 
 use super::PostIndex;
 
@@ -18,8 +21,8 @@ impl LoadPage for IndexPage {
     type Item = IndexPage;
 
     fn load<P: AsRef<Path>>(path: P) -> Result<Self::Item> {
-        let raw_content = std::fs::read_to_string(path)?;
-        let md_title = raw_content.lines().next().unwrap().to_string();
+        let raw_content = std::fs::read_to_string(&path).with_path(&path)?;
+        let md_title = raw_content.lines().next().unwrap_or_default().to_string();
         let title = strip_hash_from_title(&md_title);
         let raw_content_without_title = raw_content.lines().skip(1).collect::<Vec<_>>().join("\n");
         let content = parse_md_to_html(&raw_content_without_title);
@@ -29,7 +32,12 @@ impl LoadPage for IndexPage {
 }
 
 fn strip_hash_from_title(title: &str) -> String {
-    title.split('#').last().unwrap().trim().to_string()
+    title
+        .split('#')
+        .next_back()
+        .unwrap_or_default()
+        .trim()
+        .to_string()
 }
 
 impl IndexPage {
@@ -74,4 +82,12 @@ mod tests {
 
         assert!(index_page.post_index.is_none());
     }
+
+    #[test]
+    fn test_load_empty_index_page() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let index_page = IndexPage::load(file.path()).unwrap();
+        assert_eq!(index_page.title, "");
+        assert_eq!(index_page.content, "");
+    }
 }
@@ -1,19 +1,21 @@
-use anyhow::{bail, Result};
-use chrono::Local;
-use gray_matter::{engine::YAML, Matter};
+use chrono::{Local, NaiveDate, NaiveDateTime};
 use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{markdown::parse_md_to_html, LoadPage, PAGE_DIR};
+use crate::{
+    error::{Error, IoResultExt, Result, Span},
+    markdown::{count_words, parse_md_to_html},
+};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Post {
     pub front_matter: FrontMatter,
     pub path: PathBuf,
     pub url: String,
     pub title: String,
     pub content: String,
+    pub word_count: usize,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -33,7 +35,7 @@ impl From<&Post> for PostIndex {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct FrontMatter {
     pub title: String,
     pub tags: Option<Vec<String>>,
@@ -45,22 +47,97 @@ impl Default for FrontMatter {
         FrontMatter {
             title: "Undefined Title".to_string(),
             tags: None,
-            date: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            date: Local::now().format(DATE_FORMAT).to_string(),
         }
     }
 }
 
-impl LoadPage for Post {
-    type Item = Post;
+// how new posts are dated, shorter dates are valid too
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const DATE_FORMATS: [&str; 2] = [DATE_FORMAT, "%Y-%m-%d %H:%M"];
+
+impl FrontMatter {
+    /**
+     * Check the fields of a front matter written by onepage, loading posts
+     * accepts any title and date.
+     */
+    pub fn validate<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let invalid = |message: &str| Error::FrontMatter {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+            span: None,
+            snippet: None,
+        };
+        if self.title.trim().is_empty() {
+            return Err(invalid("`title` can't be empty"));
+        }
+        let valid_date = DATE_FORMATS
+            .iter()
+            .any(|format| NaiveDateTime::parse_from_str(&self.date, format).is_ok())
+            || NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").is_ok();
+        if !valid_date {
+            return Err(invalid(
+                "`date` must be formatted like `2022-03-29 12:00:00` or `2022-03-29`",
+            ));
+        }
+        if let Some(tags) = &self.tags {
+            if tags.iter().any(|tag| tag.trim().is_empty()) {
+                return Err(invalid("`tags` can't be empty"));
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * The post file of this front matter and its markdown content.
+     */
+    pub fn to_markdown<P: AsRef<Path>>(&self, path: P, content: &str) -> Result<String> {
+        let matter = serde_yaml::to_value(self)
+            .and_then(|mut matter| {
+                // no `tags: null` when there are none
+                if let Some(mapping) = matter.as_mapping_mut() {
+                    mapping.retain(|_, value| !value.is_null());
+                }
+                serde_yaml::to_string(&matter)
+            })
+            .map_err(|e| Error::FrontMatter {
+                path: path.as_ref().to_path_buf(),
+                message: e.to_string(),
+                span: None,
+                snippet: None,
+            })?;
+        Ok(format!("---\n{}---\n\n{}\n", matter, content.trim_end()))
+    }
+}
 
-    fn load<P: AsRef<Path>>(path: P) -> Result<Self::Item> {
-        let raw_content = std::fs::read_to_string(&path)?;
+impl Post {
+    /**
+     * Load a post, its `path` and `url` are relative to the page dir.
+     */
+    pub fn load_in<D: AsRef<Path>, P: AsRef<Path>>(page_dir: D, path: P) -> Result<Self> {
+        let raw_content = std::fs::read_to_string(&path).with_path(&path)?;
+        Self::parse_in(page_dir, path, &raw_content)
+    }
 
-        let (fm, md) = Self::read_front_matter(&raw_content, &path)?;
+    /**
+     * Parse the markdown of a post which is not read from its file,
+     * eg. unsaved edits.
+     */
+    pub fn parse_in<D: AsRef<Path>, P: AsRef<Path>>(
+        page_dir: D,
+        path: P,
+        raw_content: &str,
+    ) -> Result<Self> {
+        let (fm, md) = Self::read_front_matter(raw_content, &path)?;
 
         let title = fm.title.clone();
         let content = parse_md_to_html(&md);
-        let path = path.as_ref().strip_prefix(PAGE_DIR).unwrap().to_path_buf();
+        let word_count = count_words(&md);
+        let path = path
+            .as_ref()
+            .strip_prefix(page_dir.as_ref())
+            .unwrap_or_else(|_| path.as_ref())
+            .to_path_buf();
         Ok(Post {
             front_matter: fm,
             path: path.clone(),
@@ -71,26 +148,138 @@ impl LoadPage for Post {
                 .to_string(),
             title,
             content,
+            word_count,
         })
     }
-}
 
-impl Post {
+    /**
+     * Split the yaml front matter from the markdown content.
+     * Errors point at the line and column of the file, and name the
+     * missing or invalid field.
+     */
     pub fn read_front_matter<P: AsRef<Path>>(
         content: &str,
         path: P,
     ) -> Result<(FrontMatter, String)> {
-        let matter = Matter::<YAML>::new();
-        match matter.parse_with_struct::<FrontMatter>(content) {
-            Some(parsed) => Ok((parsed.data, parsed.content)),
-            None => bail!(
-                "Invalid front matter found in {},\ncheck the font matter details:[ https://github.com/hanpei/onepage#new-post ]",
-                path.as_ref().display(),
-            ),
+        let path = path.as_ref();
+        let (matter, md) = split_front_matter(content).ok_or_else(|| Error::FrontMatter {
+            path: path.to_path_buf(),
+            message: "a post must start with a front matter block between `---` lines, \
+                check the front matter details: [ https://github.com/hanpei/onepage#new-post ]"
+                .to_string(),
+            span: None,
+            snippet: None,
+        })?;
+        match serde_yaml::from_str::<FrontMatter>(matter) {
+            Ok(front_matter) => Ok((front_matter, md.trim().to_string())),
+            Err(e) => Err(front_matter_error(path, matter, e)),
         }
     }
 }
 
+// the opening `---` line comes before the front matter
+const FRONT_MATTER_LINE_OFFSET: usize = 1;
+const FRONT_MATTER_FIELDS: [&str; 3] = ["title", "tags", "date"];
+
+/**
+ * Split `---\n<front matter>\n---\n<content>`, `None` without a closed front matter block.
+ */
+fn split_front_matter(content: &str) -> Option<(&str, &str)> {
+    let (first_line, rest) = content.split_once('\n')?;
+    if first_line.trim_end() != "---" {
+        return None;
+    }
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+fn front_matter_error(path: &Path, matter: &str, error: serde_yaml::Error) -> Error {
+    let location = error.location();
+    let mut message = error.to_string();
+    if let Some(location) = &location {
+        let suffix = format!(" at line {} column {}", location.line(), location.column());
+        if let Some(stripped) = message.strip_suffix(&suffix) {
+            message = stripped.to_string();
+        }
+    }
+    if message.starts_with("missing field") {
+        let unknown = unknown_fields(matter);
+        if !unknown.is_empty() {
+            message = format!(
+                "{}, found unknown field(s): {}",
+                message,
+                unknown.join(", ")
+            );
+        }
+    }
+
+    let span = location.map(|location| Span {
+        line: location.line() + FRONT_MATTER_LINE_OFFSET,
+        column: location.column(),
+    });
+    Error::FrontMatter {
+        path: path.to_path_buf(),
+        message,
+        span,
+        snippet: span.map(|span| snippet(matter, span)),
+    }
+}
+
+fn unknown_fields(matter: &str) -> Vec<String> {
+    match serde_yaml::from_str::<serde_yaml::Mapping>(matter) {
+        Ok(mapping) => mapping
+            .keys()
+            .map(|key| match key.as_str() {
+                Some(key) => key.to_string(),
+                None => serde_yaml::to_string(key)
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+            })
+            .filter(|key| !FRONT_MATTER_FIELDS.contains(&key.as_str()))
+            .map(|key| format!("`{}`", key))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/**
+ * Front matter lines around the error, numbered like the file:
+ * ```text
+ *   2 | title: hello
+ * > 3 | tags: draft
+ *     |       ^
+ * ```
+ */
+fn snippet(matter: &str, span: Span) -> String {
+    let first = span
+        .line
+        .saturating_sub(2)
+        .max(1 + FRONT_MATTER_LINE_OFFSET);
+    let last = span.line + 2;
+    let width = last.to_string().len();
+    let mut snippet = String::new();
+    for (index, line) in matter.lines().enumerate() {
+        let number = index + 1 + FRONT_MATTER_LINE_OFFSET;
+        if number < first || number > last {
+            continue;
+        }
+        let marker = if number == span.line { ">" } else { " " };
+        snippet.push_str(&format!("{} {:>width$} | {}\n", marker, number, line));
+        if number == span.line {
+            let padding = " ".repeat(span.column.saturating_sub(1));
+            snippet.push_str(&format!("  {:>width$} | {}^\n", "", padding));
+        }
+    }
+    snippet
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -98,17 +287,58 @@ mod tests {
 
     #[test]
     fn test_load_post() {
-        let post = Post::load("pages/posts/test.md").unwrap();
+        let post = Post::load_in("pages", "pages/posts/test.md").unwrap();
         assert_eq!(post.front_matter.title, "Page for test");
         assert_eq!(post.front_matter.tags.unwrap(), ["draft"]);
         assert_eq!(post.front_matter.date, "2022-03-29 12:00");
 
         assert_eq!(post.title, "Page for test");
         assert_eq!(post.content, "<h1>Test</h1>\n<p>this is content</p>\n");
+        assert_eq!(post.word_count, 4);
         assert_eq!(post.url, "/posts/test.html");
         assert_eq!(post.path, PathBuf::from("posts/test.md"));
     }
 
+    #[test]
+    fn write_front_matter() {
+        let front_matter = FrontMatter {
+            title: "Release: 1.0".to_string(),
+            tags: Some(vec!["release".to_string()]),
+            date: "2022-03-29 12:00:00".to_string(),
+        };
+        assert!(front_matter.validate("new.md").is_ok());
+        let content = front_matter.to_markdown("new.md", "# Notes\n").unwrap();
+        let (parsed, md) = Post::read_front_matter(&content, "new.md").unwrap();
+        assert_eq!(parsed.title, "Release: 1.0");
+        assert_eq!(parsed.tags.unwrap(), ["release"]);
+        assert_eq!(parsed.date, "2022-03-29 12:00:00");
+        assert_eq!(md, "# Notes");
+
+        let untagged = FrontMatter {
+            tags: None,
+            ..FrontMatter::default()
+        };
+        assert!(untagged.validate("new.md").is_ok());
+        assert!(!untagged.to_markdown("new.md", "").unwrap().contains("tags"));
+
+        for invalid in [
+            FrontMatter {
+                title: " ".to_string(),
+                ..FrontMatter::default()
+            },
+            FrontMatter {
+                date: "tomorrow".to_string(),
+                ..FrontMatter::default()
+            },
+            FrontMatter {
+                tags: Some(vec!["".to_string()]),
+                ..FrontMatter::default()
+            },
+        ] {
+            assert!(invalid.validate("new.md").is_err(), "{:?}", invalid);
+        }
+    }
+
     #[test]
     fn valid_front_matter() {
         let content = r#"---
@@ -121,7 +351,7 @@ date: "2020-01-01 00:00:00"
 # test
 is test
 "#;
-        let (matter, _) = Post::read_front_matter(&content, "path/demo.md").unwrap();
+        let (matter, _) = Post::read_front_matter(content, "path/demo.md").unwrap();
         assert_eq!(matter.title, "test");
         assert_eq!(matter.tags.unwrap(), ["test", "test2"]);
         assert_eq!(matter.date, "2020-01-01 00:00:00");
@@ -134,7 +364,7 @@ title: "test"
 date: "2020-01-01 00:00:00"
 ---
 "#;
-        let (matter, _) = Post::read_front_matter(&content, "path/demo.md").unwrap();
+        let (matter, _) = Post::read_front_matter(content, "path/demo.md").unwrap();
         assert_eq!(matter.title, "test");
         assert_eq!(matter.date, "2020-01-01 00:00:00");
         assert!(matter.tags.is_none());
@@ -148,18 +378,52 @@ date: "2020-01-01 00:00:00"
         ---
         this is content
         "#;
-        let post = Post::read_front_matter(&content, "path/demo.md");
+        let post = Post::read_front_matter(content, "path/demo.md");
         assert!(post.is_err());
         post.unwrap();
     }
 
+    #[test]
+    fn front_matter_diagnostics() {
+        let content = "---\ntitle: test\ndate: 2020-01-01\ntags: draft\n---\n# test\n";
+        match Post::read_front_matter(content, "path/demo.md").unwrap_err() {
+            Error::FrontMatter {
+                path,
+                message,
+                span,
+                snippet,
+            } => {
+                assert_eq!(path, PathBuf::from("path/demo.md"));
+                assert!(message.starts_with("tags: invalid type"), "{}", message);
+                assert_eq!(span, Some(Span { line: 4, column: 7 }));
+                let snippet = snippet.unwrap();
+                assert!(snippet.contains("> 4 | tags: draft"), "{}", snippet);
+                assert!(snippet.contains("|       ^"), "{}", snippet);
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+
+        let content = "---\ntilte: test\ndate: 2020-01-01\n---\n";
+        let error = Post::read_front_matter(content, "path/demo.md").unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("missing field `title`"), "{}", message);
+        assert!(message.contains("unknown field(s): `tilte`"), "{}", message);
+    }
+
+    #[test]
+    fn unclosed_front_matter() {
+        let content = "---\ntitle: test\n# test\n";
+        let error = Post::read_front_matter(content, "path/demo.md").unwrap_err();
+        assert!(error.to_string().contains("between `---` lines"));
+    }
+
     #[test]
     fn empty_front_matter() {
         let content = r#"---
         ---
         this is content
         "#;
-        let post = Post::read_front_matter(&content, "path/demo.md");
+        let post = Post::read_front_matter(content, "path/demo.md");
         assert!(post.is_err());
     }
 }
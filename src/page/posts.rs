@@ -1,21 +1,90 @@
-use anyhow::Result;
+use rayon::prelude::*;
 use std::{
+    collections::HashMap,
+    fs,
     ops::{Deref, DerefMut},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
-use crate::{builder::LoadPage, utils};
+use crate::{
+    builder::LoadPage,
+    cache,
+    error::{Error, IoResultExt, Result},
+    utils,
+};
 
 use super::{Post, PostIndex};
 
 #[derive(Debug, Default)]
 pub struct Posts {
     inner: Vec<Post>,
+    // fingerprints of the loaded sources, by post path
+    sources: HashMap<PathBuf, String>,
 }
 
 impl Posts {
     pub fn new(inner: Vec<Post>) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            sources: HashMap::new(),
+        }
+    }
+
+    /**
+     * The markdown files of a posts dir, nested dirs included.
+     */
+    pub fn files<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
+        utils::get_files_by_walkdir(path)
+            .into_iter()
+            .filter(|file| file.extension().is_some_and(|ext| ext == "md"))
+            .collect()
+    }
+
+    /**
+     * Load posts like `load`, posts whose source didn't change since
+     * `previous` was loaded are taken from it instead of being parsed again.
+     */
+    pub fn reload<P: AsRef<Path>>(path: P, previous: &Posts) -> Result<Self> {
+        // post paths are relative to the page dir, which holds the posts dir
+        let page_dir = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+        let files = Self::files(path.as_ref());
+        let previous_posts = previous
+            .inner
+            .iter()
+            .map(|post| (post.path.as_path(), post))
+            .collect::<HashMap<_, _>>();
+
+        let results = files
+            .par_iter()
+            .map(|file| {
+                let raw_content = fs::read_to_string(file).with_path(file)?;
+                let fingerprint = cache::fingerprint(&[raw_content.as_bytes()]);
+                let path = file.strip_prefix(page_dir).unwrap_or(file);
+                let post = match previous_posts.get(path) {
+                    Some(post) if previous.sources.get(path) == Some(&fingerprint) => {
+                        Post::clone(post)
+                    }
+                    _ => Post::parse_in(page_dir, file, &raw_content)?,
+                };
+                Ok((post, fingerprint))
+            })
+            .collect::<Vec<Result<_>>>();
+
+        // report errors in file order, not in the order threads finished
+        let mut posts = Posts::default();
+        let mut errors = Vec::new();
+        for result in results {
+            match result {
+                Ok((post, fingerprint)) => {
+                    posts.sources.insert(post.path.clone(), fingerprint);
+                    posts.inner.push(post);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        Error::collect(errors)?;
+
+        Ok(posts)
     }
 
     pub fn inner(&self) -> &Vec<Post> {
@@ -37,20 +106,11 @@ impl LoadPage for Posts {
     type Item = Self;
     /**
      * Load posts from a dictionary.
+     * Files are parsed in parallel, posts keep the order of the file paths.
+     * Fails with an `Error::Build` holding the errors of all invalid files.
      */
     fn load<P: AsRef<Path>>(path: P) -> Result<Self::Item> {
-        let mut posts = Vec::new();
-        let files = utils::get_files_by_walkdir(path)
-            .into_iter()
-            .filter(|e| e.display().to_string().ends_with(".md"))
-            .collect::<Vec<_>>();
-
-        for file in files {
-            let post = Post::load(file)?;
-            posts.push(post);
-        }
-
-        Ok(Posts::new(posts))
+        Self::reload(path, &Posts::default())
     }
 }
 
@@ -90,11 +150,48 @@ mod tests {
         assert_eq!(
             paths,
             vec![
-                "posts/markdown.md",
                 "posts/hello-world.md",
+                "posts/markdown.md",
                 "posts/syntax-demo.md",
                 "posts/test.md"
             ]
         );
     }
+
+    #[test]
+    fn test_load_posts_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.md"), "---\nname: a\n---\n").unwrap();
+        std::fs::write(dir.path().join("b.md"), "no front matter").unwrap();
+        match Posts::load(dir.path()).unwrap_err() {
+            Error::Build(errors) => {
+                let paths = errors.iter().filter_map(Error::path).collect::<Vec<_>>();
+                assert_eq!(
+                    paths,
+                    vec![dir.path().join("a.md"), dir.path().join("b.md")]
+                );
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_reload_posts() {
+        let dir = tempfile::tempdir().unwrap();
+        let posts_dir = dir.path().join("posts");
+        std::fs::create_dir_all(&posts_dir).unwrap();
+        let post = |title: &str| format!("---\ntitle: {}\ndate: 2022-01-01\n---\n", title);
+        std::fs::write(posts_dir.join("a.md"), post("a")).unwrap();
+        std::fs::write(posts_dir.join("b.md"), post("b")).unwrap();
+        let mut posts = Posts::load(&posts_dir).unwrap();
+        // only parsing again would undo this
+        for post in posts.iter_mut() {
+            post.title = "cached".to_string();
+        }
+
+        std::fs::write(posts_dir.join("b.md"), post("b2")).unwrap();
+        let posts = Posts::reload(&posts_dir, &posts).unwrap();
+        let titles = posts.iter().map(|p| p.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(titles, vec!["cached", "b2"]);
+    }
 }
@@ -1,21 +1,199 @@
-use anyhow::Result;
 use axum::{
-    extract::{ws, WebSocketUpgrade},
-    http::StatusCode,
-    routing::{get, get_service},
-    Router,
+    body::Bytes,
+    body::{boxed, Body},
+    extract::{ws, ConnectInfo, Extension, OriginalUri, WebSocketUpgrade},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{any, get, post},
+    Json, Router,
 };
+use hyper::service::Service;
 use notify::{RecommendedWatcher, Watcher};
-use std::{net::SocketAddr, sync::mpsc, thread, time::Duration};
+use std::{
+    fs, io,
+    net::{IpAddr, SocketAddr, TcpListener, UdpSocket},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use axum_server::tls_rustls::RustlsConfig;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, watch as signal};
+use tower_http::compression::CompressionLayer;
+
+use crate::{
+    api::{self, ContentApi, API_PATH, API_TOKEN_ENV},
+    cache,
+    dashboard::{Dashboard, DashboardState},
+    editor::{self, Editor, EDITOR_PATH},
+    error::{Error, Result},
+    sources::{Sources, SourcesState},
+    tls::TlsCert,
+    Config, MemoryFiles, MemoryOutput, SiteBuilder, BASE_PATH, CONFIG_FILE, NOT_FOUND_PAGE,
+};
+
+// live reload client, only served in serve mode
+const LIVE_RELOAD_SCRIPT: &str = include_str!("livereload.js");
+
+/**
+ * Errors of the last failed build, shown in an overlay in the browser
+ * until a build succeeds. Empty when the last build succeeded.
+ */
+pub type BuildErrorState = Arc<RwLock<Vec<ErrorDetail>>>;
+
+/**
+ * One build error as shown in the browser overlay.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorDetail {
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl ErrorDetail {
+    /**
+     * One detail per failed file.
+     */
+    pub fn from_error(error: &Error) -> Vec<Self> {
+        match error {
+            Error::Build(errors) => errors.iter().flat_map(Self::from_error).collect(),
+            error => {
+                let location = error.location();
+                let span = location.and_then(|(_, span)| span);
+                vec![ErrorDetail {
+                    file: location.map(|(file, _)| file.display().to_string()),
+                    line: span.map(|span| span.line),
+                    column: span.map(|span| span.column),
+                    message: error.report(),
+                }]
+            }
+        }
+    }
+}
+
+/**
+ * Live reload message sent to the browsers over `/__ws` as json, eg.
+ * `{"kind":"css","path":"/assets/style.css"}`.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Reload {
+    // reload every page
+    Full,
+    // swap the stylesheet in place
+    Css { path: String },
+    // reload the browsers viewing this page or the index
+    Page { path: String },
+    // show the errors of a failed build
+    Error { errors: Vec<ErrorDetail> },
+}
+
+// editorial overview of the site in serve mode
+const DASHBOARD_PATH: &str = "/__onepage";
+
+// opens the source of a page in the editor of the developer
+const OPEN_PATH: &str = "/__open";
+
+const NOT_FOUND_HTML: &str =
+    "<!DOCTYPE html>\n<html>\n<body>\n<h1>404 Not Found</h1>\n</body>\n</html>\n";
+
+// how many ports after the requested one are tried when it is busy
+const PORT_ATTEMPTS: u16 = 20;
+
+// how long open connections get to finish once the server shuts down
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
 
-use tokio::sync::broadcast;
-use tower_http::services::ServeDir;
+// how often the watcher checks whether the server shuts down
+const WATCH_POLL: Duration = Duration::from_millis(200);
 
-use crate::{SiteBuilder, OUTPUT_DIR, PAGE_DIR};
+// how long changes are collected after the last one before rebuilding
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/**
+ * Set to `true` once the server shuts down, the watcher and the live
+ * reload sockets stop when it changes.
+ */
+pub type StopSignal = signal::Receiver<bool>;
+
+/**
+ * A running server, returned by `SiteServer::start`.
+ */
+pub struct ServerHandle {
+    addr: SocketAddr,
+    server: axum_server::Handle,
+    stop: Arc<signal::Sender<bool>>,
+    task: tokio::task::JoinHandle<Result<()>>,
+    // the dev server watches the sources on a thread of its own
+    watcher: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl ServerHandle {
+    /**
+     * The address the server listens on.
+     */
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /**
+     * Stop accepting connections, close the live reload sockets and stop
+     * watching. Requests in flight get a few seconds to finish, await
+     * `stopped` to wait for them.
+     */
+    pub fn shutdown(&self) {
+        self.stop.send_replace(true);
+        self.server.graceful_shutdown(Some(SHUTDOWN_GRACE));
+    }
+
+    /**
+     * A signal which is set once the server stops, on `shutdown` or when
+     * serving or watching failed.
+     */
+    pub fn stop_signal(&self) -> StopSignal {
+        self.stop.subscribe()
+    }
+
+    /**
+     * Wait until the server and the watcher stopped, with the error of
+     * the one which failed.
+     */
+    pub async fn stopped(self) -> Result<()> {
+        let served = self.task.await.map_err(|e| Error::Server(e.to_string()))?;
+        let watched = match self.watcher {
+            Some(watcher) => tokio::task::spawn_blocking(move || watcher.join())
+                .await
+                .map_err(|e| Error::Server(e.to_string()))?
+                .map_err(|_| Error::Server("the watcher panicked".to_string()))?,
+            None => Ok(()),
+        };
+        served.and(watched)
+    }
+}
 
 pub struct SiteServer {
     host: String,
     port: u16,
+    config: Config,
+    // open the site in the browser once the server is up
+    open: bool,
+    // serve the built output dir as is, without building or watching
+    static_only: bool,
+    // serve over https with a self-signed certificate
+    https: bool,
+    // serve the web editor of the posts
+    editor: bool,
+    // serve the json content api
+    api: bool,
 }
 
 impl Default for SiteServer {
@@ -23,105 +201,1630 @@ impl Default for SiteServer {
         Self {
             host: "127.0.0.1".to_string(),
             port: 8080,
+            config: Config::default(),
+            open: false,
+            static_only: false,
+            https: false,
+            editor: false,
+            api: false,
         }
     }
 }
 
 impl SiteServer {
-    pub fn new(address: &str) -> Self {
-        let pairs = address.split(':').collect::<Vec<&str>>();
-        let host = pairs[0].to_string();
-        let port = pairs[1].parse::<u16>().unwrap_or_default();
-        SiteServer { host, port }
+    pub fn new(address: &str) -> Result<Self> {
+        let invalid_address = || Error::Server(format!("invalid address \"{}\"", address));
+        let (host, port) = address.rsplit_once(':').ok_or_else(invalid_address)?;
+        let port = port.parse::<u16>().map_err(|_| invalid_address())?;
+        Ok(SiteServer {
+            host: host.to_string(),
+            port,
+            ..SiteServer::default()
+        })
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_open(mut self, open: bool) -> Self {
+        self.open = open;
+        self
+    }
+
+    pub fn with_static(mut self, static_only: bool) -> Self {
+        self.static_only = static_only;
+        self
+    }
+
+    pub fn with_https(mut self, https: bool) -> Self {
+        self.https = https;
+        self
+    }
+
+    /**
+     * Serve a web editor of the posts at `/__editor`, which saves them
+     * into the page dir. Not served with `with_static`.
+     */
+    pub fn with_editor(mut self, editor: bool) -> Self {
+        self.editor = editor;
+        self
+    }
+
+    /**
+     * Serve a json api to list, create, update and delete posts at
+     * `/__api/posts`, with the token of `ONEPAGE_API_TOKEN` or a generated
+     * one. Not served with `with_static`.
+     */
+    pub fn with_api(mut self, api: bool) -> Self {
+        self.api = api;
+        self
+    }
+
+    /**
+     * Bind the server and tell where the site is, with the certificate
+     * to serve it with over https.
+     */
+    fn listen(&self, tools: &DevTools) -> Result<(TcpListener, Option<TlsCert>)> {
+        let tls = match self.https {
+            true => Some(TlsCert::load_or_generate(&self.config, &self.cert_hosts())?),
+            false => None,
+        };
+        let listener = bind(&self.host, self.port)?;
+        let addr = local_addr(&listener)?;
+        print_urls(addr, self.https);
+        if let Some(editor) = &tools.editor {
+            println!(
+                "Editor on {}{}?token={}",
+                local_url(addr, self.https),
+                EDITOR_PATH,
+                editor.token()
+            );
+        }
+        if let Some(api) = &tools.api {
+            let token = match api.generated_token() {
+                Some(token) => format!("token {}", token),
+                None => format!("token from ${}", API_TOKEN_ENV),
+            };
+            println!(
+                "Content API on {}{}/posts, {}",
+                local_url(addr, self.https),
+                API_PATH,
+                token
+            );
+        }
+        println!("\n");
+        if self.open {
+            open_browser(&local_url(addr, self.https));
+        }
+        Ok((listener, tls))
     }
 
+    /**
+     * Hosts the certificate is valid for, the LAN address too when the
+     * server listens on all interfaces.
+     */
+    fn cert_hosts(&self) -> Vec<String> {
+        let mut hosts = vec![
+            "localhost".to_string(),
+            "127.0.0.1".to_string(),
+            "::1".to_string(),
+        ];
+        let extra = match self.host.parse::<IpAddr>() {
+            Ok(ip) if ip.is_unspecified() => lan_ip().map(|ip| ip.to_string()),
+            _ => Some(self.host.clone()),
+        };
+        if let Some(host) = extra {
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+        hosts
+    }
+
+    /**
+     * Serve the site until it is interrupted by ctrl-c or SIGTERM, then
+     * shut down gracefully.
+     */
     pub fn run(self) -> Result<()> {
-        let mut site = SiteBuilder::new();
-        site.build()?;
-        let (reload_channel, _) = broadcast::channel(10);
-        let tx = reload_channel.clone();
-        let addr = format!("{}:{}", self.host, self.port).parse::<SocketAddr>()?;
-        thread::spawn(move || {
-            serve(addr, reload_channel).unwrap();
-        });
+        run_until_stopped(self)
+    }
+
+    /**
+     * Build the site and serve it on the tokio runtime of the caller, the
+     * sources are watched on a thread of its own. Must be called within a
+     * tokio runtime, the server runs until the returned handle shuts it down.
+     */
+    pub async fn start(self) -> Result<ServerHandle> {
+        let (stop, stop_signal) = signal::channel(false);
+        let stop = Arc::new(stop);
+        let server = axum_server::Handle::new();
+        if self.static_only {
+            return self.start_static(stop, server);
+        }
+
+        // the dev server keeps the site in memory, only `build` writes the output dir
+        let config = self.config.clone();
+        let tools = DevTools {
+            editor: self.editor.then(|| Editor::new(&self.config)).transpose()?,
+            api: self
+                .api
+                .then(|| ContentApi::from_env(&self.config))
+                .transpose()?,
+        };
+        let (app, watcher) = {
+            let stop = stop.clone();
+            let server = server.clone();
+            let tools = tools.clone();
+            tokio::task::spawn_blocking(move || {
+                live_site(config, tools, stop_signal, move |_| {
+                    // nothing is rebuilt anymore, stop serving stale pages
+                    stop.send_replace(true);
+                    server.graceful_shutdown(Some(SHUTDOWN_GRACE));
+                })
+            })
+            .await
+            .map_err(|e| Error::Server(e.to_string()))?
+        };
 
-        watch(&mut site, tx);
+        let (listener, tls) = match self.listen(&tools) {
+            Ok(listening) => listening,
+            Err(e) => {
+                stop.send_replace(true);
+                return Err(e);
+            }
+        };
+        let addr = local_addr(&listener)?;
+        let task = spawn_app(listener, tls, app, server.clone(), stop.clone());
+        Ok(ServerHandle {
+            addr,
+            server,
+            stop,
+            task,
+            watcher: Some(watcher),
+        })
+    }
 
-        Ok(())
+    /**
+     * Preview the built output dir the way a real host serves it.
+     */
+    fn start_static(
+        self,
+        stop: Arc<signal::Sender<bool>>,
+        server: axum_server::Handle,
+    ) -> Result<ServerHandle> {
+        let output_dir = self.config.output_dir.clone();
+        if !output_dir.is_dir() {
+            return Err(Error::Server(format!(
+                "\"{}\" doesn't exist, run `onepage build` first",
+                output_dir.display()
+            )));
+        }
+        let (listener, tls) = self.listen(&DevTools::default())?;
+        let addr = local_addr(&listener)?;
+        let task = spawn_app(
+            listener,
+            tls,
+            static_app(output_dir),
+            server.clone(),
+            stop.clone(),
+        );
+        Ok(ServerHandle {
+            addr,
+            server,
+            stop,
+            task,
+            watcher: None,
+        })
     }
 }
 
 #[tokio::main]
-async fn serve(address: SocketAddr, reload_channel: broadcast::Sender<()>) -> Result<()> {
-    let app = Router::new()
-        .fallback(get_service(ServeDir::new(OUTPUT_DIR)).handle_error(
-            |error: std::io::Error| async move {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Unhandled internal error: {}", error),
+async fn run_until_stopped(server: SiteServer) -> Result<()> {
+    let handle = server.start().await?;
+    let mut stopped = handle.stop_signal();
+    tokio::select! {
+        _ = shutdown_signal() => println!("\nShutting down..."),
+        // serving or watching failed
+        _ = stopped.changed() => {}
+    }
+    handle.shutdown();
+    handle.stopped().await
+}
+
+/**
+ * Resolves on ctrl-c, or SIGTERM on unix.
+ */
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+fn local_addr(listener: &TcpListener) -> Result<SocketAddr> {
+    listener
+        .local_addr()
+        .map_err(|e| Error::Server(e.to_string()))
+}
+
+/**
+ * Bind the requested port, or the next free one when it is in use.
+ */
+fn bind(host: &str, port: u16) -> Result<TcpListener> {
+    let last = if port == 0 {
+        0
+    } else {
+        port.saturating_add(PORT_ATTEMPTS - 1)
+    };
+    for port in port..=last {
+        match TcpListener::bind((host, port)) {
+            Ok(listener) => return Ok(listener),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && port < last => {
+                println!("Port {} is in use, trying {}...", port, port + 1);
+            }
+            Err(e) => {
+                return Err(Error::Server(format!(
+                    "failed to bind {}:{}: {}",
+                    host, port, e
+                )))
+            }
+        }
+    }
+    unreachable!("the last port attempt returns")
+}
+
+/**
+ * The url to open the site locally, a server bound to all interfaces is
+ * reachable at localhost.
+ */
+fn local_url(addr: SocketAddr, https: bool) -> String {
+    let scheme = if https { "https" } else { "http" };
+    if addr.ip().is_unspecified() {
+        format!("{}://localhost:{}", scheme, addr.port())
+    } else {
+        format!("{}://{}", scheme, addr)
+    }
+}
+
+fn print_urls(addr: SocketAddr, https: bool) {
+    println!("Serving site on {}", local_url(addr, https));
+    if addr.ip().is_unspecified() {
+        if let Some(ip) = lan_ip() {
+            println!(
+                "On your network: {}",
+                local_url(SocketAddr::new(ip, addr.port()), https)
+            );
+        }
+    }
+}
+
+/**
+ * The address of the interface used for outgoing traffic, connecting
+ * a udp socket sends nothing.
+ */
+fn lan_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:80").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_loopback() && !ip.is_unspecified()).then_some(ip)
+}
+
+fn open_browser(url: &str) {
+    let mut command = if cfg!(target_os = "macos") {
+        process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        process::Command::new("xdg-open")
+    };
+    if let Err(e) = command.arg(url).spawn() {
+        println!("💥 Failed to open the browser: {}", e);
+    }
+}
+
+/**
+ * The site as a router to mount in an axum app, eg. under `/blog` with
+ * `Router::nest`. The site is built in memory once, nothing is written
+ * to the output dir. Mounted under `/blog` the site is at `/blog/`, links
+ * in the templates are root relative and need to include the path.
+ */
+pub fn site_router(config: Config) -> Result<Router> {
+    let output = MemoryOutput::new();
+    let files = output.files();
+    SiteBuilder::with_output(config, output).build()?;
+    Ok(memory_app(files))
+}
+
+/**
+ * Like `site_router`, with live reload. The sources are watched on a
+ * thread of its own until `stop` is set, changes are rebuilt and reloaded
+ * in the browsers, build errors are shown in them instead of failing.
+ */
+pub fn live_site_router(config: Config, stop: StopSignal) -> Router {
+    let (app, _) = live_site(config, DevTools::default(), stop, |result| {
+        if let Err(e) = result {
+            println!("💥 {}", e.report());
+        }
+    });
+    app
+}
+
+/**
+ * Build the site in memory and watch it, `on_exit` is called with the
+ * result of the watcher once it stopped.
+ */
+fn live_site(
+    config: Config,
+    tools: DevTools,
+    stop: StopSignal,
+    on_exit: impl FnOnce(&Result<()>) + Send + 'static,
+) -> (Router, thread::JoinHandle<Result<()>>) {
+    let output = MemoryOutput::new();
+    let files = output.files();
+    let mut site = SiteBuilder::with_output(config, output);
+    let build_error = BuildErrorState::default();
+    // keep serving on a failed build, it is rebuilt once the sources are fixed
+    if let Err(e) = site.build() {
+        println!("{}", e.report());
+        *build_error.write().unwrap() = ErrorDetail::from_error(&e);
+    }
+
+    let (reload_channel, _) = broadcast::channel(10);
+    let dashboard = Arc::new(RwLock::new(Dashboard::of(&site)));
+    let sources = Arc::new(RwLock::new(Sources::of(&site)));
+    let app = dev_app(
+        memory_app(files),
+        DevState {
+            routes: Arc::new(Mutex::new(dev_routes(tools))),
+            build_error: build_error.clone(),
+            reload_channel: reload_channel.clone(),
+            stop: stop.clone(),
+            dashboard: dashboard.clone(),
+            sources: sources.clone(),
+        },
+    );
+    let watcher = thread::spawn(move || {
+        let result = watch(&mut site, build_error, stop, |site, rebuild| {
+            *dashboard.write().unwrap() = Dashboard::of(site);
+            *sources.write().unwrap() = Sources::of(site);
+            for reload in rebuild.reloads {
+                // fails when no browser is connected
+                let _ = reload_channel.send(reload);
+            }
+        });
+        on_exit(&result);
+        result
+    });
+    (app, watcher)
+}
+
+/**
+ * A wildcard route rather than a fallback, a router with a fallback can't
+ * be nested. Of any method, the editor api of the dev routes posts.
+ */
+fn memory_app(files: MemoryFiles) -> Router {
+    Router::new()
+        .route("/*path", any(serve_memory))
+        .layer(Extension(files))
+}
+
+/**
+ * The dev tools `serve` is asked for.
+ */
+#[derive(Debug, Clone, Default)]
+struct DevTools {
+    editor: Option<Editor>,
+    api: Option<ContentApi>,
+}
+
+/**
+ * The dev routes of a live reloaded site, and what the `/__ws` socket,
+ * the `/__onepage` dashboard and `/__open` need.
+ */
+#[derive(Clone)]
+struct DevState {
+    // locked only to route a request, not while it is handled
+    routes: Arc<Mutex<Router>>,
+    build_error: BuildErrorState,
+    reload_channel: broadcast::Sender<Reload>,
+    stop: StopSignal,
+    dashboard: DashboardState,
+    sources: SourcesState,
+}
+
+/**
+ * Add the dev routes to the app, and the live reload client to its pages.
+ */
+fn dev_app(app: Router, dev: DevState) -> Router {
+    app.layer(Extension(dev))
+        .layer(middleware::from_fn(inject_live_reload))
+}
+
+/**
+ * Serve the app on the current runtime, the stop signal is set when
+ * the server stops.
+ */
+fn spawn_app(
+    listener: TcpListener,
+    tls: Option<TlsCert>,
+    app: Router,
+    server: axum_server::Handle,
+    stop: Arc<signal::Sender<bool>>,
+) -> tokio::task::JoinHandle<Result<()>> {
+    tokio::spawn(async move {
+        let result = run_app(listener, tls, app, server).await;
+        if let Err(e) = &result {
+            println!("💥 {}", e.report());
+        }
+        stop.send_replace(true);
+        result
+    })
+}
+
+/**
+ * Run the app on the listener, over TLS when there is a certificate.
+ */
+async fn run_app(
+    listener: TcpListener,
+    tls: Option<TlsCert>,
+    app: Router,
+    server: axum_server::Handle,
+) -> Result<()> {
+    // `/__open` only serves this machine
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+    let result = match tls {
+        Some(tls) => {
+            let config = RustlsConfig::from_pem(tls.cert, tls.key)
+                .await
+                .map_err(|e| Error::Server(format!("invalid certificate: {}", e)))?;
+            axum_server::from_tcp_rustls(listener, config)
+                .handle(server)
+                .serve(service)
+                .await
+        }
+        None => {
+            axum_server::from_tcp(listener)
+                .handle(server)
+                .serve(service)
+                .await
+        }
+    };
+    result.map_err(|e| Error::Server(e.to_string()))
+}
+
+/**
+ * Serve the site from memory, answering missing files with the generated
+ * 404 page when there is one. With live reload the dev routes are served
+ * too.
+ */
+async fn serve_memory(
+    Extension(files): Extension<MemoryFiles>,
+    dev: Option<Extension<DevState>>,
+    req: Request<Body>,
+) -> Response {
+    if let Some(Extension(dev)) = dev {
+        if req.uri().path().starts_with("/__") {
+            let response = dev.routes.lock().unwrap().call(req);
+            return match response.await {
+                Ok(response) => response,
+                Err(infallible) => match infallible {},
+            };
+        }
+    }
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+    let files = files.read().unwrap();
+    if let Some(path) = request_file(req.uri().path()) {
+        if let Some(content) = files.get(&path) {
+            return file_response(req.headers(), &path, content.clone(), "no-cache");
+        }
+    }
+    match files.get(Path::new(NOT_FOUND_PAGE)) {
+        Some(page) => (StatusCode::NOT_FOUND, Html(page.clone())).into_response(),
+        // html, so the live reload client shows the errors of a failed first build
+        None => (StatusCode::NOT_FOUND, Html(NOT_FOUND_HTML)).into_response(),
+    }
+}
+
+/**
+ * The dev tools, called by `serve_memory` for paths starting with `/__`,
+ * as routes of the app they would conflict with its wildcard.
+ */
+fn dev_routes(tools: DevTools) -> Router {
+    let mut routes = Router::new()
+        .route("/__ws", get(dev_socket))
+        .route(DASHBOARD_PATH, get(dashboard))
+        .route(OPEN_PATH, post(open_source));
+    if let Some(editor) = tools.editor {
+        routes = routes.merge(editor::routes(editor));
+    }
+    if let Some(api) = tools.api {
+        routes = routes.merge(api::routes(api));
+    }
+    routes.fallback(any(dev_not_found))
+}
+
+async fn dev_not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        "the editor and the api are served with `onepage serve --editor` and `--api`",
+    )
+        .into_response()
+}
+
+async fn dev_socket(ws: WebSocketUpgrade, Extension(dev): Extension<DevState>) -> Response {
+    ws.on_upgrade(|socket| handle_socket(socket, dev))
+        .into_response()
+}
+
+async fn dashboard(Extension(dev): Extension<DevState>, req: Request<Body>) -> Response {
+    let errors = dev.build_error.read().unwrap().clone();
+    match dev
+        .dashboard
+        .read()
+        .unwrap()
+        .render(&mount_path(&req), &errors)
+    {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/**
+ * A page to open the source of, its path is relative to the mount path.
+ */
+#[derive(Debug, Deserialize)]
+struct OpenRequest {
+    path: String,
+}
+
+/**
+ * Open the source of a page in the editor, only for requests from this
+ * machine as it runs a program on it.
+ */
+async fn open_source(
+    Extension(dev): Extension<DevState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    Json(open): Json<OpenRequest>,
+) -> Response {
+    if !peer.is_some_and(|ConnectInfo(addr)| is_local(addr)) {
+        return (
+            StatusCode::FORBIDDEN,
+            "sources are only opened for requests from this machine",
+        )
+            .into_response();
+    }
+    let (sources, source) = {
+        let sources = dev.sources.read().unwrap();
+        match request_file(&open.path).and_then(|file| sources.source(&file)) {
+            Some(source) => (sources.clone(), source.to_path_buf()),
+            None => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    format!("no markdown source for {}", open.path),
                 )
+                    .into_response()
+            }
+        }
+    };
+    // the editor is watched for a moment after start
+    match tokio::task::spawn_blocking(move || sources.open(&source)).await {
+        Ok(Ok(())) => StatusCode::NO_CONTENT.into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, e.report()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/**
+ * Loopback, ipv4 ones mapped to ipv6 too when listening on `::`.
+ */
+fn is_local(addr: SocketAddr) -> bool {
+    match addr.ip() {
+        IpAddr::V4(ip) => ip.is_loopback(),
+        IpAddr::V6(ip) => ip
+            .to_ipv4_mapped()
+            .map_or(ip.is_loopback(), |ip| ip.is_loopback()),
+    }
+}
+
+fn static_app(output_dir: PathBuf) -> Router {
+    Router::new()
+        .fallback(get(serve_output))
+        .layer(Extension(output_dir))
+        .layer(CompressionLayer::new())
+}
+
+/**
+ * Serve files from the output dir, answering missing files with the
+ * generated 404 page when there is one.
+ */
+async fn serve_output(Extension(output_dir): Extension<PathBuf>, req: Request<Body>) -> Response {
+    if let Some(file) = request_file(req.uri().path()) {
+        let mut path = output_dir.join(file);
+        if tokio::fs::metadata(&path)
+            .await
+            .is_ok_and(|metadata| metadata.is_dir())
+        {
+            path.push("index.html");
+        }
+        if let Ok(content) = tokio::fs::read(&path).await {
+            // pages change with every build, assets are revalidated hourly
+            let cache_control = match path.extension() {
+                Some(ext) if ext == "html" => "no-cache",
+                _ => "public, max-age=3600",
+            };
+            return file_response(req.headers(), &path, content.into(), cache_control);
+        }
+    }
+    match tokio::fs::read(output_dir.join(NOT_FOUND_PAGE)).await {
+        Ok(page) => (StatusCode::NOT_FOUND, Html(page)).into_response(),
+        Err(_) => (StatusCode::NOT_FOUND, Html(NOT_FOUND_HTML)).into_response(),
+    }
+}
+
+/**
+ * A file with its content type, cache headers and ETag, or
+ * `304 Not Modified` when the browser has this version already.
+ * The ETag is weak, the compressed bodies share it.
+ */
+fn file_response(
+    headers: &HeaderMap,
+    path: &Path,
+    content: Bytes,
+    cache_control: &'static str,
+) -> Response {
+    let tag = format!("\"{}\"", &cache::fingerprint(&[&content])[..16]);
+    // weak comparison, as If-None-Match is compared
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',')
+                .map(str::trim)
+                .any(|other| other == "*" || other.strip_prefix("W/").unwrap_or(other) == tag)
+        });
+    let etag = format!("W/{}", tag);
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    let headers = [
+        (header::CONTENT_TYPE, mime.to_string()),
+        (header::CACHE_CONTROL, cache_control.to_string()),
+        (header::ETAG, etag),
+        // responses may be compressed for the browsers which accept it
+        (header::VARY, "accept-encoding".to_string()),
+    ];
+    if not_modified {
+        (StatusCode::NOT_MODIFIED, headers).into_response()
+    } else {
+        (headers, content).into_response()
+    }
+}
+
+/**
+ * The output file of a request path, dirs are served by their index.html.
+ * `None` for paths escaping the site root.
+ */
+fn request_file(path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    let mut file = PathBuf::new();
+    for component in Path::new(decoded.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(name) => file.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if decoded.ends_with('/') || file.as_os_str().is_empty() {
+        file.push("index.html");
+    }
+    Some(file)
+}
+
+/**
+ * Add the live reload client to html responses, connecting back to the
+ * path the app is mounted at. Not in the editor, a reload would lose
+ * unsaved edits.
+ */
+async fn inject_live_reload(req: Request<Body>, next: Next<Body>) -> Response {
+    if req.uri().path().starts_with(EDITOR_PATH) {
+        return next.run(req).await;
+    }
+    let base = mount_path(&req);
+    let response = next.run(req).await;
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+    if !is_html || response.status() == StatusCode::NOT_MODIFIED {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let html = match hyper::body::to_bytes(body).await {
+        Ok(html) => html,
+        Err(e) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+        }
+    };
+    let html = inject_script(&String::from_utf8_lossy(&html), &base);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, boxed(Body::from(html)))
+}
+
+/**
+ * The path the app is mounted at with `Router::nest`, empty at the root.
+ */
+pub(crate) fn mount_path(req: &Request<Body>) -> String {
+    let path = req.uri().path();
+    let original = match req.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path(),
+        None => return String::new(),
+    };
+    // the root of a nested app is requested without the trailing slash too
+    let base = match original.strip_suffix(path) {
+        Some(base) => base,
+        None if path == "/" => original,
+        None => "",
+    };
+    base.trim_end_matches('/').to_string()
+}
+
+fn inject_script(html: &str, base: &str) -> String {
+    let script = format!(
+        "<script data-base=\"{}\">\n{}</script>\n",
+        base, LIVE_RELOAD_SCRIPT
+    );
+    match html.rfind("</body>") {
+        Some(index) => format!("{}{}{}", &html[..index], script, &html[index..]),
+        None => format!("{}{}", html, script),
+    }
+}
+
+/**
+ * What a changed file means for the site.
+ */
+#[derive(Debug, PartialEq)]
+enum Change {
+    Config,
+    Template,
+    Page(PathBuf),
+    Static(PathBuf),
+}
+
+impl Change {
+    fn of(config: &Config, path: &Path) -> Option<Self> {
+        let path = relative_path(path);
+        let is_in = |dir: &Path| path.starts_with(normalize(dir));
+        if path == normalize(Path::new(CONFIG_FILE)) {
+            Some(Change::Config)
+        } else if is_in(&config.template_dir) {
+            Some(Change::Template)
+        } else if is_in(&config.get_page_image_path()) || is_in(&config.static_dir) {
+            Some(Change::Static(path))
+        } else if is_in(&config.page_dir) {
+            Some(Change::Page(path))
+        } else {
+            None
+        }
+    }
+}
+
+/**
+ * Watch the pages, templates, static files and the config file, and
+ * rebuild as little as the change needs:
+ * - config: reload the config and rebuild everything
+ * - pages: rebuild everything
+ * - templates: reload the templates and rebuild the pages
+ * - static files: copy the changed files only
+ *
+ * Changes are collected until they settle, so an editor saving through a
+ * temp file or a `git checkout` of many files rebuild once. A change
+ * during a rebuild cancels it, it is rebuilt with the newer changes.
+ * `on_rebuild` is called after each rebuild, returns once the stop
+ * signal is set.
+ */
+pub fn watch(
+    site: &mut SiteBuilder,
+    build_error: BuildErrorState,
+    stop: StopSignal,
+    mut on_rebuild: impl FnMut(&SiteBuilder, Rebuild),
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+
+    let mut watcher = RecommendedWatcher::new(tx, Duration::from_millis(50))?;
+    watch_dirs(&mut watcher, &site.config)?;
+    // editors replace files on save, so watch the dir of the config file
+    watcher.watch(BASE_PATH, notify::RecursiveMode::NonRecursive)?;
+
+    let config = Arc::new(RwLock::new(site.config.clone()));
+    let cancel = site.cancel_flag();
+    let changed = forward_changes(rx, config.clone(), cancel.clone());
+    // changes of a cancelled rebuild are rebuilt with the next ones
+    let mut paths = Vec::new();
+    loop {
+        if *stop.borrow() {
+            return Ok(());
+        }
+        if paths.is_empty() {
+            match changed.recv_timeout(WATCH_POLL) {
+                Ok(path) => paths.push(path),
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                // the watcher is gone, nothing left to watch
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+        while let Ok(path) = changed.recv_timeout(DEBOUNCE) {
+            push_unique(&mut paths, path);
+        }
+        // changes from now on cancel the rebuild
+        cancel.store(false, Ordering::SeqCst);
+        while let Ok(path) = changed.try_recv() {
+            push_unique(&mut paths, path);
+        }
+
+        let mut changes = Vec::new();
+        for path in &paths {
+            if let Some(change) = Change::of(&site.config, path) {
+                if !site.quiet {
+                    println!("File changed: {:?}", relative_path(path));
+                }
+                push_unique(&mut changes, change);
+            }
+        }
+        if changes.is_empty() {
+            paths.clear();
+            continue;
+        }
+
+        let started = Instant::now();
+        let failed = !build_error.read().unwrap().is_empty();
+        let has_page = changes.iter().any(|c| matches!(c, Change::Page(_)));
+        let result = if changes.contains(&Change::Config) {
+            if !site.quiet {
+                println!("Reloading config...");
+            }
+            reload_config(site, &mut watcher, &config)
+                .and_then(|_| site.rebuild())
+                .map(|_| vec![Reload::Full])
+        } else if has_page || failed {
+            // a failed build is only cleared by a full rebuild
+            if !site.quiet {
+                println!("Rebuilding site...");
+            }
+            site.rebuild().map(|_| match failed {
+                true => vec![Reload::Full],
+                false => page_reloads(site, &changes),
+            })
+        } else if changes.contains(&Change::Template) {
+            if !site.quiet {
+                println!("Rebuilding site with new templates...");
+            }
+            site.rebuild_templates().map(|_| vec![Reload::Full])
+        } else {
+            update_statics(site, &changes)
+        };
+        // keep the errors for browsers connecting later, the stale pages
+        // are served until the next successful build reloads them
+        let reloads = match result {
+            Ok(reloads) => {
+                build_error.write().unwrap().clear();
+                reloads
+            }
+            Err(Error::Cancelled) => {
+                if !site.quiet {
+                    println!("Rebuild cancelled by newer changes...");
+                }
+                continue;
+            }
+            Err(e) => {
+                println!("{}", e.report());
+                let errors = ErrorDetail::from_error(&e);
+                *build_error.write().unwrap() = errors.clone();
+                vec![Reload::Error { errors }]
+            }
+        };
+        on_rebuild(
+            site,
+            Rebuild {
+                changed: std::mem::take(&mut paths),
+                elapsed: started.elapsed(),
+                reloads,
             },
-        ))
-        .route(
-            "/__ws",
-            get(|ws: WebSocketUpgrade| async move {
-                ws.on_upgrade(|socket| async move { handle_socket(socket, reload_channel).await })
-            }),
         );
+    }
+}
 
-    println!("Serving site on {}\n\n", address);
-    axum::Server::bind(&address)
-        .serve(app.into_make_service())
-        .await?;
+/**
+ * What one rebuild of the watcher did.
+ */
+#[derive(Debug)]
+pub struct Rebuild {
+    // the changed files
+    pub changed: Vec<PathBuf>,
+    pub elapsed: Duration,
+    // what the browsers need to reload, an `Error` when the build failed
+    pub reloads: Vec<Reload>,
+}
 
-    Ok(())
+impl Rebuild {
+    /**
+     * One line about the rebuild, eg.
+     * `[12:30:05] ✅ posts/hello.md changed, rebuilt in 35ms`.
+     */
+    pub fn status(&self) -> String {
+        let changed = match self.changed.as_slice() {
+            [path] => relative_path(path).display().to_string(),
+            paths => format!("{} files", paths.len()),
+        };
+        let errors = self
+            .reloads
+            .iter()
+            .map(|reload| match reload {
+                Reload::Error { errors } => errors.len(),
+                _ => 0,
+            })
+            .sum::<usize>();
+        let time = chrono::Local::now().format("%H:%M:%S");
+        match errors {
+            0 => format!(
+                "[{}] ✅ {} changed, rebuilt in {}ms",
+                time,
+                changed,
+                self.elapsed.as_millis()
+            ),
+            errors => format!(
+                "[{}] 💥 {} changed, build failed with {} error(s)",
+                time, changed, errors
+            ),
+        }
+    }
 }
 
-pub fn watch(site: &mut SiteBuilder, reload_channel: broadcast::Sender<()>) {
-    let (tx, rx) = mpsc::channel();
+/**
+ * Build the site into the output dir and keep it up to date, without
+ * serving it. Prints a status line per rebuild, until ctrl-c or SIGTERM.
+ */
+pub fn build_watch(mut site: SiteBuilder) -> Result<()> {
+    let build_error = BuildErrorState::default();
+    // keep watching on a failed build, it is rebuilt once the sources are fixed
+    if let Err(e) = site.build() {
+        println!("{}", e.report());
+        *build_error.write().unwrap() = ErrorDetail::from_error(&e);
+    }
+    site.quiet = true;
 
-    let mut watcher = RecommendedWatcher::new(tx, Duration::from_millis(50))
-        .expect("watcher failed to initialize!");
+    let (stop, stop_signal) = signal::channel(false);
+    thread::spawn(move || {
+        wait_for_shutdown();
+        stop.send_replace(true);
+    });
+    println!("👀 Watching for changes, press ctrl-c to stop");
+    watch(&mut site, build_error, stop_signal, |_, rebuild| {
+        println!("{}", rebuild.status())
+    })
+}
 
-    watcher
-        .watch(PAGE_DIR, notify::RecursiveMode::Recursive)
-        .expect("failed to watch content folder!");
+#[tokio::main(flavor = "current_thread")]
+async fn wait_for_shutdown() {
+    shutdown_signal().await;
+    println!("\nStopping...");
+}
 
-    loop {
-        match rx.recv() {
-            Ok(event) => match event {
+/**
+ * Forward the changed files of the site from the file watcher, on a
+ * thread which stops with the watcher. Every change sets the cancel flag,
+ * the rebuild in progress is superseded by it.
+ */
+fn forward_changes(
+    events: mpsc::Receiver<hotwatch::Event>,
+    config: Arc<RwLock<Config>>,
+    cancel: Arc<AtomicBool>,
+) -> mpsc::Receiver<PathBuf> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for event in events {
+            let paths = match event {
                 hotwatch::Event::Create(path)
                 | hotwatch::Event::Write(path)
-                | hotwatch::Event::Remove(path)
-                | hotwatch::Event::Rename(path, _) => {
-                    if let Some(path) = path.display().to_string().split("pages").nth(1) {
-                        println!("File changed: {:?}", path);
-                        println!("Rebuilding site...");
-                    }
-                    site.rebuild().expect("Site rebuild failed");
-                    // reload_channel
-                    //     .send(())
-                    //     .expect("livereloading message send failed");
-                    match reload_channel.send(()) {
-                        Ok(_) => (),
-                        Err(e) => println!("{}", e),
+                | hotwatch::Event::Remove(path) => vec![path],
+                hotwatch::Event::Rename(from, to) => vec![from, to],
+                _ => continue,
+            };
+            for path in paths {
+                // the output dir and the cache are written by the rebuild itself
+                if Change::of(&config.read().unwrap(), &path).is_none() {
+                    continue;
+                }
+                cancel.store(true, Ordering::SeqCst);
+                if tx.send(path).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    rx
+}
+
+fn push_unique<T: PartialEq>(items: &mut Vec<T>, item: T) {
+    if !items.contains(&item) {
+        items.push(item);
+    }
+}
+
+fn watch_dirs(watcher: &mut RecommendedWatcher, config: &Config) -> Result<()> {
+    for dir in [&config.page_dir, &config.template_dir, &config.static_dir] {
+        if dir.exists() {
+            watcher.watch(dir, notify::RecursiveMode::Recursive)?;
+        }
+    }
+    Ok(())
+}
+
+fn reload_config(
+    site: &mut SiteBuilder,
+    watcher: &mut RecommendedWatcher,
+    shared: &RwLock<Config>,
+) -> Result<()> {
+    let config = Config::load(CONFIG_FILE)?;
+    for dir in [
+        &site.config.page_dir,
+        &site.config.template_dir,
+        &site.config.static_dir,
+    ] {
+        // the dir may be gone already
+        let _ = watcher.unwatch(dir);
+    }
+    watch_dirs(watcher, &config)?;
+    *shared.write().unwrap() = config.clone();
+    site.set_config(config);
+    Ok(())
+}
+
+/**
+ * Reload the browsers viewing the changed posts, a change to any other
+ * page reloads every browser.
+ */
+fn page_reloads(site: &SiteBuilder, changes: &[Change]) -> Vec<Reload> {
+    let page_dir = normalize(&site.config.page_dir);
+    let index = normalize(&site.config.get_page_index_path());
+    let mut reloads = Vec::new();
+    for change in changes {
+        let path = match change {
+            Change::Page(path) => path,
+            _ => continue,
+        };
+        let post = site
+            .posts
+            .as_ref()
+            .iter()
+            .find(|post| page_dir.join(&post.path) == *path);
+        match post {
+            Some(post) => reloads.push(Reload::Page {
+                path: post.url.clone(),
+            }),
+            None if *path == index => reloads.push(Reload::Page {
+                path: "/".to_string(),
+            }),
+            None => return vec![Reload::Full],
+        }
+    }
+    reloads
+}
+
+/**
+ * Copy the changed static files, stylesheets are swapped in place and
+ * any other file reloads every browser.
+ */
+fn update_statics(site: &mut SiteBuilder, changes: &[Change]) -> Result<Vec<Reload>> {
+    let mut errors = Vec::new();
+    let mut reloads = Vec::new();
+    for change in changes {
+        if let Change::Static(path) = change {
+            match site.update_static(path) {
+                Ok(Some(output)) => {
+                    if !site.quiet {
+                        println!("✅ Copied \"{}\".", path.display());
                     }
+                    let url = format!("/{}", output.to_string_lossy().replace('\\', "/"));
+                    reloads.push(match output.extension() {
+                        Some(ext) if ext == "css" => Reload::Css { path: url },
+                        _ => Reload::Full,
+                    });
                 }
-                _ => {}
-            },
-            Err(e) => println!("watch error: {:?}", e),
+                Ok(None) => {}
+                Err(e) => errors.push(e),
+            }
         }
     }
+    Error::collect(errors)?;
+    if reloads.contains(&Reload::Full) {
+        reloads = vec![Reload::Full];
+    }
+    Ok(reloads)
 }
 
-async fn handle_socket(mut socket: ws::WebSocket, reload_channel: broadcast::Sender<()>) {
-    let mut rx = reload_channel.subscribe();
-    while rx.recv().await.is_ok() {
-        // println!("reload_channel recv ...");
+/**
+ * Watcher events carry absolute paths, make them relative to the site
+ * root like the paths in the config.
+ */
+fn relative_path(path: &Path) -> PathBuf {
+    let path = match std::env::current_dir().and_then(fs::canonicalize) {
+        Ok(root) => path.strip_prefix(root).unwrap_or(path),
+        Err(_) => path,
+    };
+    normalize(path)
+}
 
-        let ws_send = socket.send(ws::Message::Text("reload".to_owned()));
-        if ws_send.await.is_err() {
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+/**
+ * Push live reload messages to a browser, starting with the errors of the
+ * last build if it failed.
+ */
+async fn handle_socket(mut socket: ws::WebSocket, dev: DevState) {
+    let DevState {
+        build_error,
+        reload_channel,
+        mut stop,
+        ..
+    } = dev;
+    let mut rx = reload_channel.subscribe();
+    let errors = build_error.read().unwrap().clone();
+    if !errors.is_empty() {
+        let message = serde_json::to_string(&Reload::Error { errors }).unwrap_or_default();
+        if socket.send(ws::Message::Text(message)).await.is_err() {
+            return;
+        }
+    }
+    loop {
+        // close the socket on shutdown, so the server stops without waiting for it
+        let reload = tokio::select! {
+            reload = rx.recv() => match reload {
+                Ok(reload) => reload,
+                Err(_) => break,
+            },
+            _ = stop.changed() => break,
+        };
+        let message = match serde_json::to_string(&reload) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        if socket.send(ws::Message::Text(message)).await.is_err() {
             break;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::EDITOR_TOKEN_HEADER;
+
+    #[test]
+    fn classify_changes() {
+        let config = Config::default();
+        let change = |path: &str| Change::of(&config, Path::new(path));
+        assert_eq!(change("onepage.toml"), Some(Change::Config));
+        assert_eq!(change("./templates/base.html"), Some(Change::Template));
+        assert_eq!(
+            change("pages/posts/test.md"),
+            Some(Change::Page(PathBuf::from("pages/posts/test.md")))
+        );
+        assert_eq!(
+            change("pages/image/a.png"),
+            Some(Change::Static(PathBuf::from("pages/image/a.png")))
+        );
+        assert_eq!(
+            change("static/assets/style.css"),
+            Some(Change::Static(PathBuf::from("static/assets/style.css")))
+        );
+        assert_eq!(change(".dist.staging"), None);
+
+        let root = fs::canonicalize(BASE_PATH).unwrap();
+        assert_eq!(
+            Change::of(&config, &root.join("templates/post.html")),
+            Some(Change::Template)
+        );
+    }
+
+    #[test]
+    fn reload_messages() {
+        let json = |reload: &Reload| serde_json::to_string(reload).unwrap();
+        assert_eq!(json(&Reload::Full), r#"{"kind":"full"}"#);
+        assert_eq!(
+            json(&Reload::Css {
+                path: "/assets/style.css".to_string()
+            }),
+            r#"{"kind":"css","path":"/assets/style.css"}"#
+        );
+
+        let mut site = SiteBuilder::new();
+        site.load().unwrap();
+        let page = |path: &str| Change::Page(PathBuf::from(path));
+        assert_eq!(
+            page_reloads(&site, &[page("pages/posts/test.md")]),
+            vec![Reload::Page {
+                path: "/posts/test.html".to_string()
+            }]
+        );
+        assert_eq!(
+            page_reloads(&site, &[page("pages/index.md")]),
+            vec![Reload::Page {
+                path: "/".to_string()
+            }]
+        );
+        assert_eq!(
+            page_reloads(&site, &[page("pages/posts/removed.md")]),
+            vec![Reload::Full]
+        );
+    }
+
+    #[test]
+    fn live_reload_script() {
+        let html = inject_script("<html><body><p>hi</p></body></html>", "/blog");
+        assert!(html.starts_with("<html><body><p>hi</p><script data-base=\"/blog\">"));
+        assert!(html.ends_with("</script>\n</body></html>"));
+        assert!(inject_script("<p>hi</p>", "").starts_with("<p>hi</p><script"));
+
+        let nested = |original: &str, path: &str| {
+            let mut req = Request::builder().uri(path).body(Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(OriginalUri(original.parse().unwrap()));
+            mount_path(&req)
+        };
+        assert_eq!(nested("/blog/posts/a.html", "/posts/a.html"), "/blog");
+        assert_eq!(nested("/blog", "/"), "/blog");
+        assert_eq!(nested("/blog/", "/"), "/blog");
+        assert_eq!(nested("/index.html", "/index.html"), "");
+    }
+
+    #[test]
+    fn server_address() {
+        let server = SiteServer::new("0.0.0.0:3000").unwrap();
+        assert_eq!((server.host.as_str(), server.port), ("0.0.0.0", 3000));
+        assert!(SiteServer::new("127.0.0.1:abc").is_err());
+        assert!(SiteServer::new("127.0.0.1:70000").is_err());
+        assert!(SiteServer::new("localhost").is_err());
+    }
+
+    #[test]
+    fn bind_next_free_port() {
+        let busy = bind("127.0.0.1", 0).unwrap();
+        let port = busy.local_addr().unwrap().port();
+        let listener = bind("127.0.0.1", port).unwrap();
+        assert_ne!(listener.local_addr().unwrap().port(), port);
+
+        let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+        assert_eq!(local_url(addr, false), "http://localhost:3000");
+        let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+        assert_eq!(local_url(addr, true), "https://127.0.0.1:3000");
+    }
+
+    #[test]
+    fn local_peers() {
+        let local = |addr: &str| is_local(addr.parse().unwrap());
+        assert!(local("127.0.0.1:8080"));
+        assert!(local("[::1]:8080"));
+        assert!(local("[::ffff:127.0.0.1]:8080"));
+        assert!(!local("192.168.1.2:8080"));
+        assert!(!local("[::ffff:192.168.1.2]:8080"));
+    }
+
+    #[test]
+    fn request_files() {
+        let file = |path: &str| request_file(path).map(|file| file.display().to_string());
+        assert_eq!(file("/").unwrap(), "index.html");
+        assert_eq!(file("/posts/").unwrap(), "posts/index.html");
+        assert_eq!(
+            file("/posts/hello%20world.html").unwrap(),
+            "posts/hello world.html"
+        );
+        assert_eq!(file("/./assets/style.css").unwrap(), "assets/style.css");
+        assert!(file("/../secret").is_none());
+        assert!(file("/posts/%2e%2e/%2e%2e/secret").is_none());
+    }
+
+    #[test]
+    fn error_details() {
+        let error = Error::Build(vec![
+            Error::FrontMatter {
+                path: PathBuf::from("pages/posts/a.md"),
+                message: "missing field `title`".to_string(),
+                span: Some(crate::Span { line: 2, column: 1 }),
+                snippet: None,
+            },
+            Error::Init("oops".to_string()),
+        ]);
+        let errors = ErrorDetail::from_error(&error);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].file.as_deref(), Some("pages/posts/a.md"));
+        assert_eq!((errors[0].line, errors[0].column), (Some(2), Some(1)));
+        assert!(errors[0].message.contains("missing field `title`"));
+        assert_eq!(errors[1].file, None);
+
+        let json = serde_json::to_string(&Reload::Error {
+            errors: errors[1..].to_vec(),
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            r#"{"kind":"error","errors":[{"file":null,"line":null,"column":null,"message":"Failed to initialize site: oops"}]}"#
+        );
+    }
+
+    #[test]
+    fn file_responses() {
+        let path = Path::new("assets/style.css");
+        let content = Bytes::from("body {}");
+        let response = file_response(&HeaderMap::new(), path, content.clone(), "no-cache");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/css");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        let etag = response.headers()[header::ETAG].clone();
+        assert!(etag.to_str().unwrap().starts_with("W/\""));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        let response = file_response(&headers, path, content.clone(), "no-cache");
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let strong = etag.to_str().unwrap().trim_start_matches("W/").to_string();
+        headers.insert(header::IF_NONE_MATCH, strong.parse().unwrap());
+        let response = file_response(&headers, path, content, "no-cache");
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = file_response(&headers, path, Bytes::from("body {} a {}"), "no-cache");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn rebuild_status() {
+        let mut rebuild = Rebuild {
+            changed: vec![PathBuf::from("pages/posts/a.md")],
+            elapsed: Duration::from_millis(35),
+            reloads: vec![Reload::Full],
+        };
+        let status = rebuild.status();
+        assert!(
+            status.ends_with("✅ pages/posts/a.md changed, rebuilt in 35ms"),
+            "{}",
+            status
+        );
+
+        rebuild.changed.push(PathBuf::from("pages/posts/b.md"));
+        let error = ErrorDetail {
+            file: None,
+            line: None,
+            column: None,
+            message: "broken".to_string(),
+        };
+        rebuild.reloads = vec![Reload::Error {
+            errors: vec![error.clone(), error],
+        }];
+        let status = rebuild.status();
+        assert!(
+            status.ends_with("💥 2 files changed, build failed with 2 error(s)"),
+            "{}",
+            status
+        );
+    }
+
+    #[tokio::test]
+    async fn nested_site_router() {
+        let (stop, stop_signal) = signal::channel(false);
+        let app = Router::new()
+            .nest("/blog", site_router(Config::default()).unwrap())
+            .nest("/live", live_site_router(Config::default(), stop_signal));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = local_addr(&listener).unwrap();
+        let server = axum_server::Handle::new();
+        let task = tokio::spawn(run_app(listener, None, app, server.clone()));
+
+        let get = |path: &str| reqwest::get(format!("http://{}{}", addr, path));
+        let index = get("/blog/").await.unwrap();
+        assert_eq!(index.status(), StatusCode::OK);
+        assert!(!index.text().await.unwrap().contains("data-base"));
+        let post = get("/blog/posts/test.html").await.unwrap();
+        assert_eq!(post.status(), StatusCode::OK);
+        assert_eq!(
+            get("/blog/nope.html").await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let live = get("/live/posts/test.html")
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(live.contains("<script data-base=\"/live\">"));
+        let dashboard = get("/live/__onepage").await.unwrap().text().await.unwrap();
+        assert!(dashboard.contains("href=\"&#x2F;live&#x2F;posts&#x2F;test.html\""));
+        assert_eq!(
+            get("/blog/__onepage").await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+
+        stop.send_replace(true);
+        server.graceful_shutdown(None);
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn dev_tool_routes() {
+        let (stop, stop_signal) = signal::channel(false);
+        let config = Config::default();
+        let editor = Editor::new(&config).unwrap();
+        let tools = DevTools {
+            editor: Some(editor.clone()),
+            api: Some(ContentApi::new(&config, "secret")),
+        };
+        let (app, _) = live_site(config, tools, stop_signal, |_| {});
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = local_addr(&listener).unwrap();
+        let server = axum_server::Handle::new();
+        let task = tokio::spawn(run_app(listener, None, app, server.clone()));
+
+        let url = |path: &str| format!("http://{}{}", addr, path);
+        let client = reqwest::Client::new();
+        let page = reqwest::get(url(&format!("/__editor?token={}", editor.token())))
+            .await
+            .unwrap();
+        assert_eq!(page.status(), StatusCode::OK);
+        assert!(!page.text().await.unwrap().contains("<script data-base"));
+        assert_eq!(
+            reqwest::get(url("/__editor?token=nope"))
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            reqwest::get(url("/__editor/posts")).await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let posts = client
+            .get(url("/__editor/posts"))
+            .header(EDITOR_TOKEN_HEADER, editor.token())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(posts.status(), StatusCode::OK);
+        assert!(posts.text().await.unwrap().contains("\"posts/test.md\""));
+        let preview = |content: &str| {
+            client
+                .post(url("/__editor/preview"))
+                .header(EDITOR_TOKEN_HEADER, editor.token())
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(
+                    serde_json::json!({ "path": "posts/test.md", "content": content }).to_string(),
+                )
+                .send()
+        };
+        let html = preview("---\ntitle: Draft\ndate: 2022-01-01\n---\nunsaved")
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(html.contains("<p>unsaved</p>"), "{}", html);
+        assert_eq!(
+            preview("no front matter").await.unwrap().status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+
+        assert_eq!(
+            reqwest::get(url("/__api/posts")).await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let api = |method: reqwest::Method, path: &str| {
+            client.request(method, url(path)).bearer_auth("secret")
+        };
+        let posts = api(reqwest::Method::GET, "/__api/posts")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(posts.contains("\"slug\":\"test\""), "{}", posts);
+        let post = api(reqwest::Method::GET, "/__api/posts/test")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(post.status(), StatusCode::OK);
+        assert!(post
+            .text()
+            .await
+            .unwrap()
+            .contains("\"title\":\"Page for test\""));
+        assert_eq!(
+            api(reqwest::Method::GET, "/__api/posts/missing")
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::NOT_FOUND
+        );
+        let invalid = api(reqwest::Method::POST, "/__api/posts")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(serde_json::json!({ "title": "Invalid", "date": "soon" }).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(invalid.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(invalid.text().await.unwrap().contains("\"error\""));
+
+        let missing = client
+            .post(url(OPEN_PATH))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(serde_json::json!({ "path": "/posts/missing.html" }).to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            reqwest::get(url("/__nope")).await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+
+        stop.send_replace(true);
+        server.graceful_shutdown(None);
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.html"), "<p>hello</p>").unwrap();
+        let config = Config {
+            output_dir: dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let handle = SiteServer::new("127.0.0.1:0")
+            .unwrap()
+            .with_config(config)
+            .with_static(true)
+            .start()
+            .await
+            .unwrap();
+        let addr = handle.addr();
+        let body = reqwest::get(format!("http://{}/", addr))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "<p>hello</p>");
+
+        let stop = handle.stop_signal();
+        handle.shutdown();
+        handle.stopped().await.unwrap();
+        assert!(*stop.borrow());
+        assert!(reqwest::get(format!("http://{}/", addr)).await.is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{Error, Result},
+    SiteBuilder,
+};
+
+// editor of the shell, used when `editor_command` is not configured
+const EDITOR_ENV: &str = "EDITOR";
+// editors that need the terminal the server is running in
+const TERMINAL_EDITORS: [&str; 11] = [
+    "vi", "vim", "nvim", "nano", "pico", "micro", "hx", "kak", "joe", "ne", "ed",
+];
+// how long an editor is watched for an early exit
+const EDITOR_START_TIME: Duration = Duration::from_millis(300);
+
+/**
+ * The sources as of the last build, replaced after every rebuild.
+ */
+pub type SourcesState = Arc<RwLock<Sources>>;
+
+/**
+ * Where the pages of the dev server come from, to open them in an editor.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+    // output file, eg. `posts/hello.html`, to its markdown file
+    files: HashMap<PathBuf, PathBuf>,
+    // `editor_command` of the config
+    command: Option<String>,
+}
+
+impl Sources {
+    pub fn of(site: &SiteBuilder) -> Self {
+        let mut files = site
+            .posts
+            .as_ref()
+            .iter()
+            .map(|post| {
+                (
+                    post.path.with_extension("html"),
+                    site.config.page_dir.join(&post.path),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        files.insert(
+            PathBuf::from("index.html"),
+            site.config.get_page_index_path(),
+        );
+        Self {
+            files,
+            command: site.config.editor_command.clone(),
+        }
+    }
+
+    /**
+     * The markdown file an output file is rendered from.
+     */
+    pub fn source(&self, file: &Path) -> Option<&Path> {
+        self.files.get(file).map(PathBuf::as_path)
+    }
+
+    /**
+     * Open a source with `editor_command` or `$EDITOR`, the source is
+     * appended to the command, which is split with shell quoting rules.
+     * The editor runs without the terminal of the server, so it has to be a
+     * gui editor, eg. `code`, terminal ones like `vim` are refused. It is
+     * watched for a moment after start to report it failing, then left
+     * running.
+     */
+    pub fn open(&self, source: &Path) -> Result<()> {
+        let command = self
+            .command
+            .clone()
+            .or_else(|| env::var(EDITOR_ENV).ok())
+            .filter(|command| !command.trim().is_empty())
+            .ok_or_else(|| {
+                Error::Server(format!(
+                    "set `editor_command` in onepage.toml or ${} to open sources",
+                    EDITOR_ENV
+                ))
+            })?;
+        let words = shell_words::split(&command)
+            .map_err(|e| Error::Server(format!("invalid editor command `{}`: {}", command, e)))?;
+        let (program, args) = words
+            .split_first()
+            .ok_or_else(|| Error::Server(format!("invalid editor command `{}`", command)))?;
+        let name = Path::new(program)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        if TERMINAL_EDITORS.contains(&name) {
+            return Err(Error::Server(format!(
+                "`{}` runs in a terminal, set `editor_command` to a gui editor, eg. `code --goto`",
+                name
+            )));
+        }
+        let mut child = Command::new(program)
+            .args(args)
+            .arg(source)
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(|e| Error::Server(format!("failed to run `{}`: {}", command, e)))?;
+        // launchers like `code` hand the file over and exit right away
+        let started = Instant::now();
+        while started.elapsed() < EDITOR_START_TIME {
+            match child.try_wait() {
+                Ok(Some(status)) if status.success() => return Ok(()),
+                Ok(Some(status)) => {
+                    return Err(Error::Server(format!(
+                        "`{}` exited with {}",
+                        command, status
+                    )))
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(20)),
+                Err(e) => return Err(Error::Server(format!("failed to run `{}`: {}", command, e))),
+            }
+        }
+        // reaped once the editor exits
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, MemoryOutput};
+
+    #[test]
+    fn sources() {
+        let mut site = SiteBuilder::with_output(Config::default(), MemoryOutput::new());
+        site.build().unwrap();
+        let mut sources = Sources::of(&site);
+        assert_eq!(
+            sources.source(Path::new("posts/test.html")),
+            Some(Path::new("pages/posts/test.md"))
+        );
+        assert_eq!(
+            sources.source(Path::new("index.html")),
+            Some(Path::new("pages/index.md"))
+        );
+        assert_eq!(sources.source(Path::new("posts/missing.html")), None);
+
+        sources.command = Some("true --wait".to_string());
+        assert!(sources.open(Path::new("pages/posts/test.md")).is_ok());
+        sources.command = Some("onepage-missing-editor".to_string());
+        assert!(sources.open(Path::new("pages/posts/test.md")).is_err());
+        // quoted words are kept whole, the source is `$0`
+        sources.command = Some(r#"sh -c 'test "$0" = "my posts/a b.md"'"#.to_string());
+        assert!(sources.open(Path::new("my posts/a b.md")).is_ok());
+        sources.command = Some("sh -c 'exit 3'".to_string());
+        assert!(sources
+            .open(Path::new("pages/posts/test.md"))
+            .unwrap_err()
+            .to_string()
+            .contains("exit status: 3"));
+        sources.command = Some("'sh -c".to_string());
+        assert!(sources.open(Path::new("pages/posts/test.md")).is_err());
+        sources.command = Some("/usr/bin/vim".to_string());
+        assert!(sources
+            .open(Path::new("pages/posts/test.md"))
+            .unwrap_err()
+            .to_string()
+            .contains("runs in a terminal"));
+    }
+}
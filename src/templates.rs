@@ -1,39 +1,295 @@
-use anyhow::Result;
 use serde::Serialize;
-use tera::Tera;
-
-lazy_static::lazy_static! {
-    pub static ref TEMPLATES: Tera = {
-        let mut tera = match Tera::new("templates/**/*") {
-            Ok(t) => t,
-            Err(e) => {
-                println!("Parsing error(s): {}", e);
-                ::std::process::exit(1);
-            }
-        };
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tera::{ErrorKind, Tera};
+
+use crate::{
+    cache,
+    error::{Error, IoResultExt, Result, Span, TemplateError},
+    utils,
+};
+
+/**
+ * Tera templates of the site, with the fingerprint of their sources.
+ */
+#[derive(Debug, Default)]
+pub struct Templates {
+    tera: Tera,
+    fingerprint: String,
+}
+
+impl Templates {
+    /**
+     * Parse all templates in the template dir.
+     */
+    pub fn load<P: AsRef<Path>>(template_dir: P) -> Result<Self> {
+        let template_dir = template_dir.as_ref();
+        let glob = template_dir.join("**").join("*");
+        let mut tera = Tera::new(&glob.to_string_lossy()).map_err(|source| {
+            let (file, span) = parse_error_location(&source);
+            Error::Template(Box::new(TemplateError {
+                template: file
+                    .as_deref()
+                    .and_then(Path::file_name)
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| template_dir.display().to_string()),
+                page: None,
+                file,
+                span,
+                context_keys: Vec::new(),
+                source,
+            }))
+        })?;
         tera.autoescape_on(vec![]);
 
-        println!("\t- {} Tera templates loaded", tera.templates.len());
-        tera
-    };
+        Ok(Self {
+            tera,
+            fingerprint: fingerprint(template_dir)?,
+        })
+    }
+
+    /**
+     * Number of loaded templates.
+     */
+    pub fn count(&self) -> usize {
+        self.tera.templates.len()
+    }
+
+    /**
+     * Render a template, `page` is the source file the data comes from.
+     */
+    pub fn render(
+        &self,
+        template_name: &str,
+        data: &impl Serialize,
+        page: Option<&Path>,
+    ) -> Result<String> {
+        let template_error = |source, context_keys| {
+            let (file, span) = self.locate_error(template_name, &source);
+            Error::Template(Box::new(TemplateError {
+                template: template_name.to_string(),
+                page: page.map(Path::to_path_buf),
+                file,
+                span,
+                context_keys,
+                source,
+            }))
+        };
+        let ctx = tera::Context::from_serialize(data)
+            .map_err(|source| template_error(source, Vec::new()))?;
+        self.tera.render(template_name, &ctx).map_err(|source| {
+            let context_keys = match ctx.clone().into_json() {
+                serde_json::Value::Object(map) => map.keys().cloned().collect(),
+                _ => Vec::new(),
+            };
+            template_error(source, context_keys)
+        })
+    }
+
+    /**
+     * Find the template file, and the line using the missing variable,
+     * filter or function of a render error.
+     * The template itself is searched first, then its parents.
+     */
+    fn locate_error(
+        &self,
+        template_name: &str,
+        error: &tera::Error,
+    ) -> (Option<PathBuf>, Option<Span>) {
+        let template = match self.tera.get_template(template_name) {
+            Ok(template) => template,
+            Err(_) => return (None, None),
+        };
+        let files = std::iter::once(template_name)
+            .chain(template.parents.iter().map(String::as_str))
+            .filter_map(|name| self.tera.get_template(name).ok()?.path.as_ref())
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        if let Some(name) = missing_name(error) {
+            for file in &files {
+                if let Some(span) = fs::read_to_string(file)
+                    .ok()
+                    .and_then(|source| find_in_tags(&source, &name))
+                {
+                    return (Some(file.clone()), Some(span));
+                }
+            }
+        }
+        (files.into_iter().next(), None)
+    }
+
+    pub fn has_template(&self, template_name: &str) -> bool {
+        self.tera
+            .get_template_names()
+            .any(|name| name == template_name)
+    }
+
+    /**
+     * Fingerprint of every template file, pages depend on all of them
+     * because of template inheritance.
+     */
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+}
+
+/**
+ * The name of the variable, filter, test or function a render error
+ * complains about.
+ */
+fn missing_name(error: &tera::Error) -> Option<String> {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(cause) = source {
+        if let Some(error) = cause.downcast_ref::<tera::Error>() {
+            match &error.kind {
+                ErrorKind::FilterNotFound(name)
+                | ErrorKind::TestNotFound(name)
+                | ErrorKind::FunctionNotFound(name) => return Some(name.clone()),
+                ErrorKind::Msg(message) => {
+                    // "Variable `foo.bar` not found in context while rendering 'post.html'"
+                    if let Some(rest) = message.strip_prefix("Variable `") {
+                        if let Some((name, _)) = rest.split_once('`') {
+                            return Some(name.to_string());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        source = cause.source();
+    }
+    None
 }
 
-pub fn render_template(template_name: &str, data: &impl Serialize) -> Result<String> {
-    let ctx = tera::Context::from_serialize(data)?;
-    Ok(TEMPLATES.render(template_name, &ctx)?)
+/**
+ * Position of the first whole-word use of `name` inside a `{{ }}` or `{% %}` tag.
+ */
+fn find_in_tags(source: &str, name: &str) -> Option<Span> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+    for (index, line) in source.lines().enumerate() {
+        if !line.contains("{{") && !line.contains("{%") {
+            continue;
+        }
+        for (offset, _) in line.match_indices(name) {
+            let before = line[..offset].chars().next_back();
+            let after = line[offset + name.len()..].chars().next();
+            if !before.is_some_and(is_ident) && !after.is_some_and(is_ident) {
+                return Some(Span {
+                    line: index + 1,
+                    column: line[..offset].chars().count() + 1,
+                });
+            }
+        }
+    }
+    None
+}
+
+/**
+ * File and position of a template parse error, tera reports them as
+ * `Failed to parse "templates/post.html"` followed by ` --> 3:1`.
+ */
+fn parse_error_location(error: &tera::Error) -> (Option<PathBuf>, Option<Span>) {
+    let message = error.to_string();
+    let file = message
+        .split_once("Failed to parse \"")
+        .and_then(|(_, rest)| rest.split_once('"'))
+        .map(|(file, _)| PathBuf::from(file));
+    let span = message
+        .split_once(" --> ")
+        .and_then(|(_, rest)| rest.lines().next())
+        .and_then(|position| position.trim().split_once(':'))
+        .and_then(|(line, column)| {
+            Some(Span {
+                line: line.parse().ok()?,
+                column: column.parse().ok()?,
+            })
+        });
+    (file, span)
+}
+
+fn fingerprint(template_dir: &Path) -> Result<String> {
+    let mut parts = Vec::new();
+    for file in utils::get_files_by_walkdir(template_dir) {
+        parts.push(file.display().to_string().into_bytes());
+        parts.push(std::fs::read(&file).with_path(&file)?);
+    }
+    let parts = parts.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    Ok(cache::fingerprint(&parts))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::TEMPLATE_DIR;
 
     #[test]
     fn test_render_template() {
+        let templates = Templates::load(TEMPLATE_DIR).unwrap();
         let mut ctx = tera::Context::new();
         ctx.insert("title", "test title");
         ctx.insert("content", "hello world");
-        let rendered = TEMPLATES.render("post.html", &ctx).unwrap();
+        let rendered = templates
+            .render("post.html", &ctx.into_json(), None)
+            .unwrap();
 
         println!("{}", rendered);
     }
+
+    #[test]
+    fn test_has_template() {
+        let templates = Templates::load(TEMPLATE_DIR).unwrap();
+        assert!(templates.has_template("post.html"));
+        assert!(templates.has_template("404.html"));
+        assert!(!templates.has_template("missing.html"));
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("post.html"), "<h1>\n{{ title").unwrap();
+        let error = Templates::load(dir.path()).unwrap_err();
+        match &error {
+            Error::Template(error) => {
+                assert_eq!(error.template, "post.html");
+                assert!(error.file.as_ref().unwrap().ends_with("post.html"));
+                assert_eq!(error.span.unwrap().line, 2);
+            }
+            _ => panic!("expected template error"),
+        }
+        assert!(error.report().contains("expected"));
+    }
+
+    #[test]
+    fn test_render_error_location() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("base.html"),
+            "<html>\n{% block body %}{% endblock %}\n<p>{{ site.titel }}</p>\n</html>",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("post.html"),
+            "{% extends \"base.html\" %}\n{% block body %}{{ content }}{% endblock %}",
+        )
+        .unwrap();
+        let templates = Templates::load(dir.path()).unwrap();
+        let data = serde_json::json!({ "content": "hello", "site": { "title": "x" } });
+        let error = templates
+            .render("post.html", &data, Some(Path::new("pages/posts/a.md")))
+            .unwrap_err();
+        match &error {
+            Error::Template(error) => {
+                assert!(error.file.as_ref().unwrap().ends_with("base.html"));
+                assert_eq!(error.span, Some(Span { line: 3, column: 7 }));
+                assert_eq!(error.context_keys, vec!["content", "site"]);
+            }
+            _ => panic!("expected template error"),
+        }
+        let report = error.report();
+        assert!(report.contains("for pages/posts/a.md"));
+        assert!(report.contains("Variable `site.titel` not found"));
+        assert!(report.contains("available context: `content`, `site`"));
+    }
 }
@@ -0,0 +1,112 @@
+use rcgen::{DnType, SanType};
+use std::{fs, net::IpAddr, path::Path};
+
+use crate::{
+    error::{Error, IoResultExt, Result},
+    Config,
+};
+
+/**
+ * PEM encoded certificate and private key of the dev server.
+ */
+#[derive(Debug, Clone)]
+pub struct TlsCert {
+    pub cert: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+impl TlsCert {
+    /**
+     * Load the self-signed certificate cached in the cache dir, a new one
+     * is generated when there is none or it was made for other hosts.
+     * Browsers warn about it until it is trusted.
+     */
+    pub fn load_or_generate(config: &Config, hosts: &[String]) -> Result<Self> {
+        let cert_path = config.get_cert_path();
+        let key_path = config.get_cert_key_path();
+        let hosts_path = config.get_cert_hosts_path();
+        let cached_hosts = fs::read_to_string(&hosts_path).unwrap_or_default();
+        if cached_hosts == hosts.join("\n") && cert_path.is_file() && key_path.is_file() {
+            return Ok(Self {
+                cert: fs::read(&cert_path).with_path(&cert_path)?,
+                key: fs::read(&key_path).with_path(&key_path)?,
+            });
+        }
+
+        println!("🏃🏻 Generating a self-signed certificate ...");
+        let cert = Self::generate(hosts)?;
+        fs::create_dir_all(&config.cache_dir).with_path(&config.cache_dir)?;
+        fs::write(&cert_path, &cert.cert).with_path(&cert_path)?;
+        write_private(&key_path, &cert.key)?;
+        fs::write(&hosts_path, hosts.join("\n")).with_path(&hosts_path)?;
+        Ok(cert)
+    }
+
+    /**
+     * Generate a self-signed certificate for the hosts, offline.
+     */
+    pub fn generate(hosts: &[String]) -> Result<Self> {
+        let mut params = rcgen::CertificateParams::default();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "onepage dev server");
+        params.subject_alt_names = hosts
+            .iter()
+            .map(|host| match host.parse::<IpAddr>() {
+                Ok(ip) => SanType::IpAddress(ip),
+                Err(_) => SanType::DnsName(host.clone()),
+            })
+            .collect();
+        let cert = rcgen::Certificate::from_params(params)
+            .map_err(|e| Error::Server(format!("failed to generate a certificate: {}", e)))?;
+        let pem = cert
+            .serialize_pem()
+            .map_err(|e| Error::Server(format!("failed to generate a certificate: {}", e)))?;
+        Ok(Self {
+            cert: pem.into_bytes(),
+            key: cert.serialize_private_key_pem().into_bytes(),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_path(path)?;
+    file.write_all(content).with_path(path)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    fs::write(path, content).with_path(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_cert() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            cache_dir: dir.path().join(".onepage"),
+            ..Config::default()
+        };
+        let hosts = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+        let cert = TlsCert::load_or_generate(&config, &hosts).unwrap();
+        assert!(String::from_utf8_lossy(&cert.cert).starts_with("-----BEGIN CERTIFICATE-----"));
+        assert!(String::from_utf8_lossy(&cert.key).contains("PRIVATE KEY"));
+
+        let cached = TlsCert::load_or_generate(&config, &hosts).unwrap();
+        assert_eq!(cached.cert, cert.cert);
+
+        let other = TlsCert::load_or_generate(&config, &hosts[..1]).unwrap();
+        assert_ne!(other.cert, cert.cert);
+    }
+}
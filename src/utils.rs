@@ -1,14 +1,16 @@
-use anyhow::Result;
 use std::{
-    fs,
+    fs, io,
     path::{Path, PathBuf},
 };
 
+use crate::error::{IoResultExt, Result};
+
 /**
  * get all file paths in a directory
  */
 pub fn get_files_by_walkdir<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
     walkdir::WalkDir::new(path)
+        .sort_by_file_name()
         .into_iter()
         .filter_map(|e| e.ok())
         .filter(|e| e.file_type().is_file())
@@ -23,19 +25,73 @@ pub fn get_files_by_walkdir<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
  */
 pub fn copy_files(src: &Path, dst: &Path) -> Result<()> {
     if src.is_dir() {
-        fs::create_dir_all(dst)?;
-        for entry in fs::read_dir(src)? {
-            let entry = entry?;
+        fs::create_dir_all(dst).with_path(dst)?;
+        for entry in fs::read_dir(src).with_path(src)? {
+            let entry = entry.with_path(src)?;
             let src = entry.path();
-            let dst = dst.join(src.file_name().unwrap());
+            let dst = dst.join(entry.file_name());
             copy_files(&src, &dst)?;
         }
     } else {
-        fs::copy(src, dst)?;
+        fs::copy(src, dst).with_path(src)?;
+    }
+    Ok(())
+}
+
+/**
+ * hard link files of src directory into dst directory, files are copied
+ * where hard links are not supported
+ * @param src: dictionary of source files
+ * @param dst: dictionary of destination files
+ * @param paths: files relative to src, missing ones are skipped
+ */
+pub fn link_files<'a>(
+    src: &Path,
+    dst: &Path,
+    paths: impl IntoIterator<Item = &'a Path>,
+) -> Result<()> {
+    for path in paths {
+        let src = src.join(path);
+        if !src.is_file() {
+            continue;
+        }
+        let dst = dst.join(path);
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent).with_path(parent)?;
+        }
+        if fs::hard_link(&src, &dst).is_err() {
+            fs::copy(&src, &dst).with_path(&src)?;
+        }
     }
     Ok(())
 }
 
+/**
+ * exchange two directories at once, false where the platform or the file
+ * system can't, the caller then has to rename them one after the other
+ * @param a: first directory
+ * @param b: second directory
+ */
+#[cfg(target_os = "linux")]
+pub fn exchange_dirs(a: &Path, b: &Path) -> io::Result<bool> {
+    use rustix::{
+        fs::{renameat_with, RenameFlags, CWD},
+        io::Errno,
+    };
+
+    match renameat_with(CWD, a, CWD, b, RenameFlags::EXCHANGE) {
+        Ok(()) => Ok(true),
+        // kernels before 3.15, or file systems without exchange support
+        Err(Errno::NOSYS) | Err(Errno::INVAL) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn exchange_dirs(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -58,7 +114,7 @@ mod tests {
         });
 
         let dst = PathBuf::from("tmp/dst/");
-        copy_files(&src.as_path(), &dst.as_path()).unwrap();
+        copy_files(src.as_path(), dst.as_path()).unwrap();
 
         let outputs = get_files_by_walkdir(&dst)
             .into_iter()
@@ -72,6 +128,38 @@ mod tests {
         assert!(inputs.iter().all(|item| outputs.contains(item)));
     }
 
+    #[test]
+    fn link_files_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        let dst = tmp.path().join("dst");
+        fs::create_dir_all(src.join("1")).unwrap();
+        fs::write(src.join("1/1.md"), "1").unwrap();
+        fs::write(src.join("extra.md"), "extra").unwrap();
+
+        let paths = [Path::new("1/1.md"), Path::new("missing.md")];
+        link_files(src.as_path(), dst.as_path(), paths).unwrap();
+        assert_eq!(fs::read_to_string(dst.join("1/1.md")).unwrap(), "1");
+        assert!(!dst.join("extra.md").exists());
+        assert!(!dst.join("missing.md").exists());
+    }
+
+    #[test]
+    fn exchange_dirs_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let a = tmp.path().join("a");
+        let b = tmp.path().join("b");
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(a.join("a.md"), "a").unwrap();
+
+        if exchange_dirs(&a, &b).unwrap() {
+            assert!(!a.join("a.md").exists());
+            assert_eq!(fs::read_to_string(b.join("a.md")).unwrap(), "a");
+        }
+        assert!(exchange_dirs(&a, &tmp.path().join("missing")).is_err());
+    }
+
     #[test]
     fn test_get_md_files_by_walkdir() {
         let result = get_files_by_walkdir("pages")
@@ -82,11 +170,11 @@ mod tests {
         assert_eq!(
             result,
             vec![
-                "pages/posts/markdown.md",
+                "pages/index.md",
                 "pages/posts/hello-world.md",
+                "pages/posts/markdown.md",
                 "pages/posts/syntax-demo.md",
-                "pages/posts/test.md",
-                "pages/index.md"
+                "pages/posts/test.md"
             ]
         )
     }